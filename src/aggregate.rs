@@ -0,0 +1,218 @@
+use crate::tracker::Scrape;
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// A strategy for combining the scrape statistics for a single info hash
+/// reported by multiple trackers
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Aggregate {
+    /// Take the largest value of each statistic
+    #[default]
+    Max,
+    /// Add up the values of each statistic
+    Sum,
+    /// Take the median value of each statistic, rounding down
+    Median,
+}
+
+impl Aggregate {
+    /// Combine the given scrapes, each field independently.  Returns `None`
+    /// if there are no scrapes.  Each combined statistic is based only on
+    /// those scrapes for which it is known, and the result is marked
+    /// as estimated if any of the scrapes are.  Details specific to a single
+    /// tracker, such as warnings, are not carried over.
+    pub(crate) fn combine<I>(self, scrapes: I) -> Option<Scrape>
+    where
+        I: IntoIterator<Item = Scrape>,
+    {
+        let mut complete = Vec::new();
+        let mut incomplete = Vec::new();
+        let mut downloaded = Vec::new();
+        let mut estimated = false;
+        let mut any = false;
+        for s in scrapes {
+            complete.extend(s.complete);
+            incomplete.extend(s.incomplete);
+            downloaded.extend(s.downloaded);
+            estimated |= s.estimated;
+            any = true;
+        }
+        if !any {
+            return None;
+        }
+        Some(Scrape {
+            complete: self.combine_values(complete),
+            incomplete: self.combine_values(incomplete),
+            downloaded: self.combine_values(downloaded),
+            estimated,
+            ..Scrape::default()
+        })
+    }
+
+    fn combine_values(self, mut values: Vec<u32>) -> Option<u32> {
+        match self {
+            Aggregate::Max => values.into_iter().max(),
+            Aggregate::Sum => values.into_iter().reduce(u32::saturating_add),
+            Aggregate::Median => {
+                values.sort_unstable();
+                let mid = values.len() / 2;
+                match values.len() {
+                    0 => None,
+                    n if n % 2 == 1 => Some(values[mid]),
+                    _ => Some(values[mid - 1].midpoint(values[mid])),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrapes() -> [Scrape; 4] {
+        [
+            Scrape {
+                complete: Some(10),
+                incomplete: Some(0),
+                downloaded: Some(32),
+                ..Scrape::default()
+            },
+            Scrape {
+                complete: Some(105),
+                incomplete: Some(42),
+                downloaded: Some(1337),
+                ..Scrape::default()
+            },
+            Scrape {
+                complete: Some(7),
+                incomplete: Some(3),
+                downloaded: Some(40),
+                ..Scrape::default()
+            },
+            Scrape {
+                complete: Some(12),
+                incomplete: Some(5),
+                downloaded: Some(u32::MAX),
+                ..Scrape::default()
+            },
+        ]
+    }
+
+    #[test]
+    fn combine_max() {
+        assert_eq!(
+            Aggregate::Max.combine(scrapes()),
+            Some(Scrape {
+                complete: Some(105),
+                incomplete: Some(42),
+                downloaded: Some(u32::MAX),
+                ..Scrape::default()
+            })
+        );
+    }
+
+    #[test]
+    fn combine_sum() {
+        assert_eq!(
+            Aggregate::Sum.combine(scrapes()),
+            Some(Scrape {
+                complete: Some(134),
+                incomplete: Some(50),
+                downloaded: Some(u32::MAX),
+                ..Scrape::default()
+            })
+        );
+    }
+
+    #[test]
+    fn combine_median() {
+        assert_eq!(
+            Aggregate::Median.combine(scrapes()),
+            Some(Scrape {
+                complete: Some(11),
+                incomplete: Some(4),
+                downloaded: Some(688),
+                ..Scrape::default()
+            })
+        );
+        assert_eq!(
+            Aggregate::Median.combine(scrapes().into_iter().take(3)),
+            Some(Scrape {
+                complete: Some(10),
+                incomplete: Some(3),
+                downloaded: Some(40),
+                ..Scrape::default()
+            })
+        );
+    }
+
+    #[test]
+    fn combine_unknown_downloads() {
+        let mut scrapes = scrapes();
+        scrapes[1].downloaded = None;
+        scrapes[3].downloaded = None;
+        assert_eq!(
+            Aggregate::Sum.combine(scrapes.clone()),
+            Some(Scrape {
+                complete: Some(134),
+                incomplete: Some(50),
+                downloaded: Some(72),
+                ..Scrape::default()
+            })
+        );
+        for s in &mut scrapes {
+            s.downloaded = None;
+        }
+        assert_eq!(
+            Aggregate::Max.combine(scrapes),
+            Some(Scrape {
+                complete: Some(105),
+                incomplete: Some(42),
+                downloaded: None,
+                ..Scrape::default()
+            })
+        );
+    }
+
+    #[test]
+    fn combine_unknown_counts() {
+        let mut scrapes = scrapes();
+        scrapes[1].complete = None;
+        for s in &mut scrapes {
+            s.incomplete = None;
+        }
+        assert_eq!(
+            Aggregate::Max.combine(scrapes),
+            Some(Scrape {
+                complete: Some(12),
+                incomplete: None,
+                downloaded: Some(u32::MAX),
+                ..Scrape::default()
+            })
+        );
+    }
+
+    #[test]
+    fn combine_estimated() {
+        let mut scrapes = scrapes();
+        scrapes[2].estimated = true;
+        scrapes[2].downloaded = None;
+        assert_eq!(
+            Aggregate::Max.combine(scrapes),
+            Some(Scrape {
+                complete: Some(105),
+                incomplete: Some(42),
+                downloaded: Some(u32::MAX),
+                estimated: true,
+                ..Scrape::default()
+            })
+        );
+    }
+
+    #[test]
+    fn combine_nothing() {
+        assert_eq!(Aggregate::Sum.combine([]), None);
+    }
+}
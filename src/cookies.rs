@@ -0,0 +1,125 @@
+use reqwest::cookie::Jar;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use url::Url;
+
+/// Prefix that curl & browsers put on the lines of cookie files for cookies
+/// with the `HttpOnly` attribute; such lines are not comments
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+/// Load the cookies in the given Netscape-format cookie file (as written by
+/// curl, wget, and various browser extensions) into a cookie jar.  Expired
+/// cookies are skipped.
+pub(crate) fn load_cookie_file(path: &Path) -> Result<Jar, CookieFileError> {
+    let src = fs::read_to_string(path).map_err(|source| CookieFileError::Read {
+        path: path.to_owned(),
+        source,
+    })?;
+    let jar = Jar::default();
+    for (cookie, url) in parse_cookie_file(&src).map_err(|lineno| CookieFileError::Parse {
+        path: path.to_owned(),
+        lineno,
+    })? {
+        jar.add_cookie_str(&cookie, &url);
+    }
+    Ok(jar)
+}
+
+/// Parse the contents of a cookie file into a list of `Set-Cookie`-style
+/// cookie strings paired with URLs at which they could have been set.  On
+/// failure, the number of the invalid line is returned.
+fn parse_cookie_file(src: &str) -> Result<Vec<(String, Url)>, usize> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let mut cookies = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        let line = line.strip_prefix(HTTP_ONLY_PREFIX).unwrap_or(line);
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        let &[
+            domain,
+            include_subdomains,
+            path,
+            secure,
+            expires,
+            name,
+            value,
+        ] = fields.as_slice()
+        else {
+            return Err(i + 1);
+        };
+        let Ok(expires) = expires.parse::<u64>() else {
+            return Err(i + 1);
+        };
+        // An expiration time of 0 marks a session cookie.
+        if expires != 0 && expires <= now {
+            continue;
+        }
+        let host = domain.trim_start_matches('.');
+        let secure = secure.eq_ignore_ascii_case("TRUE");
+        let scheme = if secure { "https" } else { "http" };
+        let Ok(url) = Url::parse(&format!("{scheme}://{host}{path}")) else {
+            return Err(i + 1);
+        };
+        let mut cookie = format!("{name}={value}; Path={path}");
+        if include_subdomains.eq_ignore_ascii_case("TRUE") {
+            cookie.push_str("; Domain=");
+            cookie.push_str(host);
+        }
+        if secure {
+            cookie.push_str("; Secure");
+        }
+        cookies.push((cookie, url));
+    }
+    Ok(cookies)
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum CookieFileError {
+    #[error("failed to read cookie file {}", .path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("invalid entry on line {lineno} of cookie file {}", .path.display())]
+    Parse { path: PathBuf, lineno: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_cookies() {
+        let src = concat!(
+            "# Netscape HTTP Cookie File\n",
+            "\n",
+            ".tracker.example.com\tTRUE\t/\tFALSE\t0\tuid\t1234\n",
+            "#HttpOnly_tracker.example.org\tFALSE\t/announce\tTRUE\t0\tpass\tabc=def\n",
+            "old.example.net\tFALSE\t/\tFALSE\t1\tstale\tyes\n",
+        );
+        assert_eq!(
+            parse_cookie_file(src).unwrap(),
+            [
+                (
+                    "uid=1234; Path=/; Domain=tracker.example.com".to_owned(),
+                    Url::parse("http://tracker.example.com/").unwrap()
+                ),
+                (
+                    "pass=abc=def; Path=/announce; Secure".to_owned(),
+                    Url::parse("https://tracker.example.org/announce").unwrap()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_bad_cookie_line() {
+        let src = "# Netscape HTTP Cookie File\ntracker.example.com\tFALSE\t/\n";
+        assert_eq!(parse_cookie_file(src), Err(2));
+    }
+}
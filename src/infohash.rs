@@ -1,14 +1,25 @@
-use data_encoding::{DecodeError, HEXLOWER_PERMISSIVE};
-use std::borrow::Cow;
+use crate::util::add_bytes_query_param;
+use data_encoding::{BASE32, DecodeError, HEXLOWER_PERMISSIVE};
+use serde::{Serialize, Serializer};
 use std::fmt;
 use thiserror::Error;
 use url::Url;
 
 #[derive(Copy, Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub(crate) struct InfoHash([u8; InfoHash::LENGTH]);
+pub(crate) enum InfoHash {
+    /// A BitTorrent v1 info hash (SHA-1)
+    V1([u8; InfoHash::V1_LENGTH]),
+    /// A BitTorrent v2 info hash (SHA-256; BEP 52)
+    V2([u8; InfoHash::V2_LENGTH]),
+}
 
 impl InfoHash {
-    pub(crate) const LENGTH: usize = 20;
+    pub(crate) const V1_LENGTH: usize = 20;
+    pub(crate) const V2_LENGTH: usize = 32;
+
+    /// Multihash prefix (SHA-256 function code & digest length) used for v2
+    /// info hashes in `urn:btmh:` magnet links
+    const MULTIHASH_PREFIX: &str = "1220";
 
     pub(crate) fn from_hex(s: &str) -> Result<InfoHash, InfoHashError> {
         HEXLOWER_PERMISSIVE
@@ -17,29 +28,104 @@ impl InfoHash {
             .try_into()
     }
 
+    /// Parse an info hash in RFC 4648 base32, as sometimes used in magnet
+    /// links.  Lowercase letters are accepted.
+    pub(crate) fn from_base32(s: &str) -> Result<InfoHash, InfoHashError> {
+        BASE32
+            .decode(s.to_ascii_uppercase().as_bytes())
+            .map_err(InfoHashError::InvalidBase32)?
+            .try_into()
+    }
+
+    /// Parse a v2 info hash given as a hex-encoded SHA-256 multihash, as used
+    /// in `urn:btmh:` magnet links
+    pub(crate) fn from_multihash(s: &str) -> Result<InfoHash, InfoHashError> {
+        let Some(hex) = s.strip_prefix(InfoHash::MULTIHASH_PREFIX) else {
+            return Err(InfoHashError::InvalidMultihash);
+        };
+        match InfoHash::from_hex(hex)? {
+            ih @ InfoHash::V2(_) => Ok(ih),
+            InfoHash::V1(_) => Err(InfoHashError::InvalidMultihash),
+        }
+    }
+
+    /// Parse a v1 info hash given as a 40-character hex string or a
+    /// 32-character base32 string, as in `urn:btih:` magnet links
+    pub(crate) fn from_btih(s: &str) -> Result<InfoHash, InfoHashError> {
+        match s.len() {
+            40 => InfoHash::from_hex(s),
+            32 => InfoHash::from_base32(s),
+            n => Err(InfoHashError::InvalidV1StringLength(n)),
+        }
+    }
+
+    pub(crate) fn to_base32(self) -> String {
+        BASE32.encode(self.as_bytes())
+    }
+
     pub(crate) fn as_bytes(&self) -> &[u8] {
-        self.0.as_slice()
+        match self {
+            InfoHash::V1(bs) => bs.as_slice(),
+            InfoHash::V2(bs) => bs.as_slice(),
+        }
+    }
+
+    /// Return the bytes of the info hash as sent to trackers.  Per BEP 52, v2
+    /// info hashes are truncated to 20 bytes.
+    pub(crate) fn tracker_bytes(&self) -> &[u8] {
+        match self {
+            InfoHash::V1(bs) => bs.as_slice(),
+            InfoHash::V2(bs) => &bs[..InfoHash::V1_LENGTH],
+        }
+    }
+
+    /// Return the info hash as it is known to trackers, i.e., with v2 info
+    /// hashes truncated to 20 bytes
+    pub(crate) fn truncated(self) -> InfoHash {
+        match self {
+            InfoHash::V1(_) => self,
+            InfoHash::V2(bs) => {
+                let mut v1 = [0; InfoHash::V1_LENGTH];
+                v1.copy_from_slice(&bs[..InfoHash::V1_LENGTH]);
+                InfoHash::V1(v1)
+            }
+        }
     }
 
     pub(crate) fn add_query_param(&self, url: &mut Url) {
-        add_bytes_query_param(url, "info_hash", &self.0);
+        add_bytes_query_param(url, "info_hash", self.tracker_bytes());
     }
 }
 
 impl fmt::Display for InfoHash {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        for b in self.0 {
+        for b in self.as_bytes() {
             write!(f, "{b:02x}")?;
         }
         Ok(())
     }
 }
 
+impl Serialize for InfoHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl std::str::FromStr for InfoHash {
     type Err = InfoHashError;
 
+    /// Parse an info hash given as a 40-character hex string, a 32-character
+    /// base32 string, a 64-character hex string (v2), a 56-character padded
+    /// base32 string (v2, as output by `to_base32()`), or a 68-character hex
+    /// multihash (v2)
     fn from_str(s: &str) -> Result<InfoHash, InfoHashError> {
-        InfoHash::from_hex(s)
+        match s.len() {
+            40 | 64 => InfoHash::from_hex(s),
+            32 | 56 => InfoHash::from_base32(s),
+            68 => InfoHash::from_multihash(s),
+            n => Err(InfoHashError::InvalidStringLength(n)),
+        }
     }
 }
 
@@ -47,9 +133,12 @@ impl TryFrom<&[u8]> for InfoHash {
     type Error = InfoHashError;
 
     fn try_from(bs: &[u8]) -> Result<InfoHash, InfoHashError> {
-        match bs.try_into() {
-            Ok(barray) => Ok(InfoHash(barray)),
-            Err(_) => Err(InfoHashError::InvalidLength(bs.len())),
+        if let Ok(barray) = <[u8; InfoHash::V1_LENGTH]>::try_from(bs) {
+            Ok(InfoHash::V1(barray))
+        } else if let Ok(barray) = <[u8; InfoHash::V2_LENGTH]>::try_from(bs) {
+            Ok(InfoHash::V2(barray))
+        } else {
+            Err(InfoHashError::InvalidLength(bs.len()))
         }
     }
 }
@@ -58,35 +147,28 @@ impl TryFrom<Vec<u8>> for InfoHash {
     type Error = InfoHashError;
 
     fn try_from(bs: Vec<u8>) -> Result<InfoHash, InfoHashError> {
-        match bs.try_into() {
-            Ok(barray) => Ok(InfoHash(barray)),
-            Err(bs) => Err(InfoHashError::InvalidLength(bs.len())),
-        }
+        InfoHash::try_from(bs.as_slice())
     }
 }
 
 #[derive(Copy, Clone, Debug, Eq, Error, PartialEq)]
 pub(crate) enum InfoHashError {
-    #[error("info hash is invalid hexadecimal")]
-    InvalidHex(#[source] DecodeError),
-    #[error("info hash is {0} bytes long, expected 20")]
+    #[error("info hash is invalid hexadecimal: {0}")]
+    InvalidHex(DecodeError),
+    #[error("info hash is invalid base32: {0}")]
+    InvalidBase32(DecodeError),
+    #[error("info hash is not a hex-encoded SHA-256 multihash")]
+    InvalidMultihash,
+    #[error(
+        "info hash is {0} characters long, expected 40 (hex), 32 (base32), 64 (v2 hex), 56 (v2 base32), or 68 (v2 multihash)"
+    )]
+    InvalidStringLength(usize),
+    #[error("v1 info hash is {0} characters long, expected 40 (hex) or 32 (base32)")]
+    InvalidV1StringLength(usize),
+    #[error("info hash is {0} bytes long, expected 20 or 32")]
     InvalidLength(usize),
 }
 
-fn add_bytes_query_param(url: &mut Url, key: &str, value: &[u8]) {
-    static SENTINEL: &str = "ADD_BYTES_QUERY_PARAM";
-    url.query_pairs_mut()
-        .encoding_override(Some(&|s| {
-            if s == SENTINEL {
-                Cow::from(value.to_vec())
-            } else {
-                Cow::from(s.as_bytes())
-            }
-        }))
-        .append_pair(key, SENTINEL)
-        .encoding_override(None);
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -106,6 +188,88 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_base32_info_hash() {
+        let info_hash = "FDCVDFXVO5J4ICWOW35VQYL6NGK2P3O3"
+            .parse::<InfoHash>()
+            .unwrap();
+        assert_eq!(
+            info_hash.to_string(),
+            "28c55196f57753c40aceb6fb58617e6995a7eddb"
+        );
+        assert_eq!(info_hash.to_base32(), "FDCVDFXVO5J4ICWOW35VQYL6NGK2P3O3");
+        let info_hash = "fdcvdfxvo5j4icwow35vqyl6ngk2p3o3"
+            .parse::<InfoHash>()
+            .unwrap();
+        assert_eq!(
+            info_hash.to_string(),
+            "28c55196f57753c40aceb6fb58617e6995a7eddb"
+        );
+    }
+
+    #[test]
+    fn test_bad_info_hash_strings() {
+        assert!(matches!(
+            "28C55196F57753C40ACEB6FB58617E6995A7EDDX".parse::<InfoHash>(),
+            Err(InfoHashError::InvalidHex(_))
+        ));
+        assert!(matches!(
+            "FDCVDFXVO5J4ICWOW35VQYL6NGK2P3O1".parse::<InfoHash>(),
+            Err(InfoHashError::InvalidBase32(_))
+        ));
+        assert_eq!(
+            "28C55196F57753C4".parse::<InfoHash>(),
+            Err(InfoHashError::InvalidStringLength(16))
+        );
+    }
+
+    #[test]
+    fn test_v2_info_hash() {
+        let info_hash = "CAF1E1C30E81CB361B9EE167C4AA64228A7FA4FA9F6105232B28AD099F3A302E"
+            .parse::<InfoHash>()
+            .unwrap();
+        assert!(matches!(info_hash, InfoHash::V2(_)));
+        assert_eq!(
+            info_hash.to_string(),
+            "caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e"
+        );
+        assert_eq!(
+            info_hash.truncated().to_string(),
+            "caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa"
+        );
+        assert_eq!(info_hash.tracker_bytes(), info_hash.truncated().as_bytes());
+    }
+
+    #[test]
+    fn test_v2_base32_round_trip() {
+        let info_hash = "caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e"
+            .parse::<InfoHash>()
+            .unwrap();
+        let b32 = info_hash.to_base32();
+        assert_eq!(b32.len(), 56);
+        assert_eq!(b32.parse::<InfoHash>().unwrap(), info_hash);
+        assert_eq!(
+            b32.to_ascii_lowercase().parse::<InfoHash>().unwrap(),
+            info_hash
+        );
+    }
+
+    #[test]
+    fn test_multihash_info_hash() {
+        let info_hash = "1220caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e"
+            .parse::<InfoHash>()
+            .unwrap();
+        assert_eq!(
+            info_hash.to_string(),
+            "caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e"
+        );
+        assert_eq!(
+            "1114caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e"
+                .parse::<InfoHash>(),
+            Err(InfoHashError::InvalidMultihash)
+        );
+    }
+
     #[test]
     fn test_add_query_param() {
         let info_hash = "28C55196F57753C40ACEB6FB58617E6995A7EDDB"
@@ -118,4 +282,17 @@ mod tests {
             "http://tracker.example.com:8080/announce?here=there&info_hash=%28%C5Q%96%F5wS%C4%0A%CE%B6%FBXa%7Ei%95%A7%ED%DB"
         );
     }
+
+    #[test]
+    fn test_add_v2_query_param() {
+        let info_hash = "28C55196F57753C40ACEB6FB58617E6995A7EDDB000102030405060708090A0B"
+            .parse::<InfoHash>()
+            .unwrap();
+        let mut url = Url::parse("http://tracker.example.com:8080/announce").unwrap();
+        info_hash.add_query_param(&mut url);
+        assert_eq!(
+            url.as_str(),
+            "http://tracker.example.com:8080/announce?info_hash=%28%C5Q%96%F5wS%C4%0A%CE%B6%FBXa%7Ei%95%A7%ED%DB"
+        );
+    }
 }
@@ -0,0 +1,163 @@
+use crate::infohash::{InfoHash, InfoHashError};
+use thiserror::Error;
+use url::Url;
+
+/// The parts of a magnet link that are relevant to scraping
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Magnet {
+    /// The info hashes from the `xt` parameters: `urn:btih:` for v1 and
+    /// `urn:btmh:` for v2.  Hybrid torrents have one of each.
+    pub(crate) info_hashes: Vec<InfoHash>,
+    /// The display name (`dn` parameter), if any
+    pub(crate) name: Option<String>,
+    /// The tracker URLs (`tr` parameters), in the order given
+    pub(crate) trackers: Vec<String>,
+}
+
+impl std::str::FromStr for Magnet {
+    type Err = MagnetError;
+
+    fn from_str(s: &str) -> Result<Magnet, MagnetError> {
+        let url = Url::parse(s)?;
+        if url.scheme() != "magnet" {
+            return Err(MagnetError::NotMagnet);
+        }
+        let mut info_hashes = Vec::new();
+        let mut name = None;
+        let mut trackers = Vec::new();
+        for (k, v) in url.query_pairs() {
+            match k.as_ref() {
+                "xt" => {
+                    let ih = if let Some(hash) = v.strip_prefix("urn:btih:") {
+                        InfoHash::from_btih(hash)?
+                    } else if let Some(hash) = v.strip_prefix("urn:btmh:") {
+                        InfoHash::from_multihash(hash)?
+                    } else {
+                        continue;
+                    };
+                    if !info_hashes.contains(&ih) {
+                        info_hashes.push(ih);
+                    }
+                }
+                "dn" => name = Some(v.into_owned()),
+                "tr" => trackers.push(v.into_owned()),
+                _ => (),
+            }
+        }
+        if info_hashes.is_empty() {
+            return Err(MagnetError::NoInfoHash);
+        }
+        Ok(Magnet {
+            info_hashes,
+            name,
+            trackers,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+pub(crate) enum MagnetError {
+    #[error("invalid magnet URI")]
+    Url(#[from] url::ParseError),
+    #[error("not a magnet URI")]
+    NotMagnet,
+    #[error("magnet URI does not contain a BitTorrent info hash")]
+    NoInfoHash,
+    #[error(transparent)]
+    InfoHash(#[from] InfoHashError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_magnet() {
+        let magnet = "magnet:?xt=urn:btih:28C55196F57753C40ACEB6FB58617E6995A7EDDB&dn=Some+Torrent&tr=udp%3A%2F%2Ftracker.opentrackr.org%3A1337%2Fannounce&tr=http%3A%2F%2Ftracker.example.com%2Fannounce"
+            .parse::<Magnet>()
+            .unwrap();
+        assert_eq!(
+            magnet,
+            Magnet {
+                info_hashes: vec![
+                    "28c55196f57753c40aceb6fb58617e6995a7eddb"
+                        .parse::<InfoHash>()
+                        .unwrap()
+                ],
+                name: Some("Some Torrent".into()),
+                trackers: vec![
+                    "udp://tracker.opentrackr.org:1337/announce".into(),
+                    "http://tracker.example.com/announce".into(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_base32_magnet() {
+        let magnet = "magnet:?xt=urn:btih:FDCVDFXVO5J4ICWOW35VQYL6NGK2P3O3"
+            .parse::<Magnet>()
+            .unwrap();
+        assert_eq!(
+            magnet,
+            Magnet {
+                info_hashes: vec![
+                    "28c55196f57753c40aceb6fb58617e6995a7eddb"
+                        .parse::<InfoHash>()
+                        .unwrap()
+                ],
+                name: None,
+                trackers: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_hybrid_magnet() {
+        let magnet = "magnet:?xt=urn:btih:631a31dd0a46257d5078c0dee4e66e26f73e42ac&xt=urn:btmh:1220d8dd32ac93357c368556af3ac1d95c9d76bd0dff6fa9833ecdac3d53134efabb&dn=bittorrent-v1-v2-hybrid-test"
+            .parse::<Magnet>()
+            .unwrap();
+        assert_eq!(
+            magnet,
+            Magnet {
+                info_hashes: vec![
+                    "631a31dd0a46257d5078c0dee4e66e26f73e42ac"
+                        .parse::<InfoHash>()
+                        .unwrap(),
+                    "d8dd32ac93357c368556af3ac1d95c9d76bd0dff6fa9833ecdac3d53134efabb"
+                        .parse::<InfoHash>()
+                        .unwrap(),
+                ],
+                name: Some("bittorrent-v1-v2-hybrid-test".into()),
+                trackers: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_magnet_no_info_hash() {
+        assert_eq!(
+            "magnet:?dn=foo".parse::<Magnet>(),
+            Err(MagnetError::NoInfoHash)
+        );
+    }
+
+    #[test]
+    fn parse_magnet_bad_info_hash() {
+        assert!(matches!(
+            "magnet:?xt=urn:btih:28C55196F57753C4".parse::<Magnet>(),
+            Err(MagnetError::InfoHash(_))
+        ));
+    }
+
+    #[test]
+    fn parse_magnet_btih_v2_hash() {
+        assert_eq!(
+            "magnet:?xt=urn:btih:caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e"
+                .parse::<Magnet>(),
+            Err(MagnetError::InfoHash(InfoHashError::InvalidV1StringLength(
+                64
+            )))
+        );
+    }
+}
@@ -1,47 +1,443 @@
+mod aggregate;
+mod cookies;
 mod infohash;
+mod magnet;
+mod target;
+mod torrent;
 mod tracker;
 mod util;
+use crate::aggregate::Aggregate;
+use crate::cookies::load_cookie_file;
 use crate::infohash::InfoHash;
-use crate::tracker::{Scrape, Tracker};
+use crate::target::{Target, TargetArg, TargetArgError};
+use crate::tracker::cache::ConnectionCache;
+use crate::tracker::socks::SocksProxy;
+use crate::tracker::{
+    AnnounceEvent, AnnounceParams, AnnounceResult, Announcement, IpFamily, Scrape, ScrapeResult,
+    Tracker, TrackerError, TrackerOptions, TrackerUrlError,
+    dht::{DhtOptions, DhtTracker},
+    http::HttpOptions,
+    udp::{RetryPolicy, UdpOptions},
+    ws::WsOptions,
+};
+use crate::util::read_list_file;
 use anyhow::Context;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use futures_util::{StreamExt, stream};
+use reqwest::header::{HeaderName, HeaderValue, InvalidHeaderName, InvalidHeaderValue};
+use reqwest::{Certificate, Proxy};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
 use std::io::{self, ErrorKind, IsTerminal, Write, stderr, stdout};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::path::PathBuf;
 use std::process::ExitCode;
+use std::sync::Arc;
 use std::time::Duration;
+use thiserror::Error;
 use tracing::Level;
 use tracing_subscriber::{filter::Targets, fmt::time::OffsetTime, prelude::*};
+use url::Url;
 
 /// Scrape BitTorrent trackers for swarm statistics
 ///
 /// Visit <https://github.com/jwodder/trscrape> for more information.
 #[derive(Clone, Debug, Eq, Parser, PartialEq)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Arguments {
+    /// Instead of outputting each tracker's statistics separately, output a
+    /// single set of statistics for each torrent, combined across all
+    /// trackers using the given strategy [default: max]
+    #[arg(
+        long,
+        value_enum,
+        value_name = "STRATEGY",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "max"
+    )]
+    aggregate: Option<Aggregate>,
+
+    /// Also estimate the size of each torrent's swarm by looking it up in the
+    /// BitTorrent DHT.  The DHT is then treated as an additional tracker
+    /// named "dht".
+    #[arg(long)]
+    dht: bool,
+
+    /// Start DHT lookups from the given node instead of the default
+    /// bootstrap nodes.  This option can be given multiple times.
+    #[arg(long, value_name = "HOST:PORT")]
+    dht_bootstrap: Vec<String>,
+
+    /// Also scrape the torrents listed in the given file, one per line, in any
+    /// of the forms accepted on the command line.  Blank lines and lines
+    /// starting with '#' are ignored.  Use '-' to read from standard input.
+    #[arg(long, value_name = "PATH")]
+    hash_file: Vec<patharg::InputArg>,
+
+    /// If a tracker fails to respond to a scrape request or does not support
+    /// scraping, get the numbers of seeders & leechers from an announce
+    /// request instead.  The number of downloads will then be unknown.
+    #[arg(long)]
+    fallback: bool,
+
+    /// Send at most INT info hashes in a single scrape request to an HTTP
+    /// tracker
+    #[arg(long, default_value = "50", value_name = "INT")]
+    http_batch_size: NonZeroUsize,
+
+    /// Scrape at most INT trackers at once
+    #[arg(short, long, default_value = "8", value_name = "INT")]
+    jobs: NonZeroUsize,
+
+    /// Keep HTTP scrape request URLs at most INT bytes long, splitting the
+    /// info hashes across more requests if necessary
+    #[arg(long, default_value_t = 4096, value_name = "INT")]
+    max_url_length: usize,
+
+    /// Also scrape the trackers listed in the given file, one URL per line.
+    /// Blank lines and lines starting with '#' are ignored.  Use '-' to read
+    /// from standard input.
+    #[arg(long, value_name = "PATH")]
+    tracker_file: Vec<patharg::InputArg>,
+
+    /// Send at most INT info hashes in a single scrape request to a UDP
+    /// tracker
+    #[arg(long, default_value = "74", value_name = "INT")]
+    udp_batch_size: NonZeroUsize,
+
+    /// Send at most INT info hashes in a single scrape request to a
+    /// WebSocket tracker
+    #[arg(long, default_value = "50", value_name = "INT")]
+    ws_batch_size: NonZeroUsize,
+
+    #[command(flatten)]
+    common: CommonArgs,
+
+    #[command(flatten)]
+    http: HttpArgs,
+
+    #[command(flatten)]
+    udp: UdpArgs,
+
+    /// The URLs of the HTTP, UDP, or WebSocket trackers to scrape, plus the
+    /// torrents to scrape, given as info hashes (v1 as 40-character hex or
+    /// 32-character base32; v2 as 64-character hex, 56-character base32, or
+    /// 68-character hex multihash), magnet links, or paths to .torrent files.
+    ///
+    /// Each torrent is scraped at each tracker.  Each torrent given as a magnet
+    /// link or .torrent file is also scraped at the trackers listed in it.
+    #[arg(value_name = "TRACKER|INFOHASH|MAGNET|TORRENT")]
+    inputs: Vec<InputArg>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Subcommand)]
+enum Command {
+    /// Announce torrents to a tracker and show the peers it returns
+    Announce(AnnounceArgs),
+}
+
+#[derive(Args, Clone, Debug, Eq, PartialEq)]
+struct AnnounceArgs {
+    /// Report having downloaded INT bytes
+    #[arg(long, default_value_t = 0, value_name = "INT")]
+    downloaded: u64,
+
+    /// The event to announce
+    #[arg(long, value_enum, default_value_t)]
+    event: AnnounceEvent,
+
+    /// Report having INT bytes left to download
+    #[arg(long, default_value_t = 0, value_name = "INT")]
+    left: u64,
+
+    /// Ask the tracker for at most INT peers [default: let the tracker decide]
+    #[arg(long, value_name = "INT")]
+    numwant: Option<u32>,
+
+    /// Report listening for peers on the given port
+    #[arg(short, long, default_value_t = 6881)]
+    port: u16,
+
+    /// Report having uploaded INT bytes
+    #[arg(long, default_value_t = 0, value_name = "INT")]
+    uploaded: u64,
+
+    #[command(flatten)]
+    common: CommonArgs,
+
+    #[command(flatten)]
+    http: HttpArgs,
+
+    #[command(flatten)]
+    udp: UdpArgs,
+
+    /// The URL of the HTTP, UDP, or WebSocket tracker to announce to
+    tracker: Tracker,
+
+    /// The torrents to announce, given in any of the forms accepted by the
+    /// main command
+    #[arg(required = true, value_name = "INFOHASH|MAGNET|TORRENT")]
+    targets: Vec<TargetArg>,
+}
+
+/// Options shared by the main command and subcommands that are not specific
+/// to any one kind of tracker
+#[derive(Args, Clone, Debug, Eq, PartialEq)]
+struct CommonArgs {
+    /// Output info hashes in base32 instead of hex
+    #[arg(long)]
+    base32: bool,
+
+    /// Only communicate with trackers over IPv4
+    #[arg(short = '4', long, conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// Only communicate with trackers over IPv6
+    #[arg(short = '6', long)]
+    ipv6: bool,
+
     /// Output JSON lines
     #[arg(short = 'J', long)]
     json: bool,
 
-    /// Wait at most INT seconds for the tracker to respond to our scrape
-    /// request
+    /// Wait at most INT seconds for each request to a tracker to complete
     #[arg(short, long, default_value_t = 30, value_name = "INT")]
     timeout: u64,
 
     /// Emit logs of network activity
     #[arg(long)]
     trace: bool,
+}
 
-    /// The URL of an HTTP or UDP tracker to scrape
-    tracker: Tracker,
+impl CommonArgs {
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+
+    /// Convert the `--ipv4` and `--ipv6` options to the IP address family to
+    /// restrict communication with trackers to, if any
+    fn family(&self) -> Option<IpFamily> {
+        if self.ipv4 {
+            Some(IpFamily::V4)
+        } else if self.ipv6 {
+            Some(IpFamily::V6)
+        } else {
+            None
+        }
+    }
+
+    fn printer(&self) -> Printer {
+        if self.json {
+            Printer::json(self.base32)
+        } else {
+            Printer::text(self.base32)
+        }
+    }
+}
+
+/// Options controlling communication with HTTP trackers, shared by the main
+/// command and subcommands
+#[derive(Args, Clone, Debug, Eq, PartialEq)]
+struct HttpArgs {
+    /// Trust the TLS root certificates in the given PEM file in addition to
+    /// the system's.  This option can be given multiple times.
+    #[arg(long, value_name = "PATH")]
+    ca_cert: Vec<PathBuf>,
+
+    /// Send the cookies in the given Netscape-format cookie file to HTTP
+    /// trackers
+    #[arg(long, value_name = "PATH")]
+    cookie_file: Option<PathBuf>,
+
+    /// Send the given header in requests to HTTP trackers.  This option can
+    /// be given multiple times.
+    #[arg(short = 'H', long = "header", value_name = "NAME: VALUE")]
+    headers: Vec<HeaderArg>,
+
+    /// Do not verify the TLS certificates of HTTP trackers.  This is
+    /// dangerous.
+    #[arg(long)]
+    insecure: bool,
 
-    /// Up to 50 info hashes of torrents to scrape, given as 40-character hex
-    /// strings
-    #[arg(num_args = 0..=50)]
-    hashes: Vec<InfoHash>,
+    /// Accept non-conforming responses from HTTP trackers where possible:
+    /// missing statistics are treated as unknown, out-of-range numbers are
+    /// clamped, and trailing data after a response is ignored
+    #[arg(long)]
+    lenient: bool,
+
+    /// Send requests to HTTP trackers through the given HTTP, HTTPS, or SOCKS5
+    /// proxy
+    #[arg(long, value_name = "URL")]
+    proxy: Option<Url>,
+
+    /// Send the given User-Agent header in requests to HTTP trackers
+    #[arg(long, value_name = "STRING")]
+    user_agent: Option<String>,
 }
 
+impl HttpArgs {
+    fn apply(self, options: &mut HttpOptions) -> anyhow::Result<()> {
+        for path in self.ca_cert {
+            let pem =
+                fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?;
+            let certs = Certificate::from_pem_bundle(&pem)
+                .with_context(|| format!("failed to parse certificates in {}", path.display()))?;
+            options.root_certs.extend(certs);
+        }
+        if let Some(path) = self.cookie_file {
+            options.cookies = Some(Arc::new(load_cookie_file(&path)?));
+        }
+        for HeaderArg(name, value) in self.headers {
+            options.headers.append(name, value);
+        }
+        options.insecure = self.insecure;
+        options.lenient = self.lenient;
+        if let Some(url) = self.proxy {
+            options.proxy = Some(Proxy::all(url).context("invalid proxy URL")?);
+        }
+        options.user_agent = self.user_agent;
+        Ok(())
+    }
+}
+
+/// A `--header` argument
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct HeaderArg(HeaderName, HeaderValue);
+
+impl std::str::FromStr for HeaderArg {
+    type Err = HeaderArgError;
+
+    fn from_str(s: &str) -> Result<HeaderArg, HeaderArgError> {
+        let Some((name, value)) = s.split_once(':') else {
+            return Err(HeaderArgError::NoColon);
+        };
+        let name = name.trim().parse::<HeaderName>()?;
+        let value = value.trim().parse::<HeaderValue>()?;
+        Ok(HeaderArg(name, value))
+    }
+}
+
+#[derive(Debug, Error)]
+enum HeaderArgError {
+    #[error("header must be of the form \"NAME: VALUE\"")]
+    NoColon,
+    #[error("invalid header name")]
+    Name(#[from] InvalidHeaderName),
+    #[error("invalid header value")]
+    Value(#[from] InvalidHeaderValue),
+}
+
+/// Options controlling communication with UDP trackers, shared by the main
+/// command and subcommands
+#[derive(Args, Clone, Debug, Eq, PartialEq)]
+struct UdpArgs {
+    /// Multiply the wait for a reply from a UDP tracker by INT after each
+    /// retransmission
+    #[arg(long = "udp-backoff", default_value = "2", value_name = "INT")]
+    backoff: NonZeroU32,
+
+    /// Store the connection IDs issued by UDP trackers in the given file, and
+    /// reuse them in later runs until they expire
+    #[arg(long = "udp-connection-cache", value_name = "PATH")]
+    connection_cache: Option<PathBuf>,
+
+    /// Consider UDP tracker connection IDs valid for INT seconds after they
+    /// are obtained
+    #[arg(
+        long = "udp-connection-lifetime",
+        default_value_t = 60,
+        value_name = "INT"
+    )]
+    connection_lifetime: u64,
+
+    /// Wait INT seconds for a reply to a request to a UDP tracker before
+    /// first retransmitting it
+    #[arg(long = "udp-initial-timeout", default_value_t = 15, value_name = "INT")]
+    initial_timeout: u64,
+
+    /// Retransmit an unanswered request to a UDP tracker at most INT times
+    /// before giving up
+    #[arg(long = "udp-max-retries", default_value_t = 8, value_name = "INT")]
+    max_retries: u32,
+
+    /// Relay all communication with UDP trackers through the given SOCKS5
+    /// proxy.  With a `socks5h://` URL, tracker hostnames are resolved by the
+    /// proxy.
+    #[arg(id = "udp_proxy", long = "udp-proxy", value_name = "URL")]
+    proxy: Option<SocksProxy>,
+
+    /// Do not send the path & query string of UDP tracker URLs in requests.
+    /// Use this for trackers that choke on BEP 41 options.
+    #[arg(long = "no-udp-url-data")]
+    no_url_data: bool,
+}
+
+impl UdpArgs {
+    fn apply(self, options: &mut UdpOptions) -> anyhow::Result<()> {
+        options.retry = RetryPolicy {
+            initial_timeout: Duration::from_secs(self.initial_timeout),
+            backoff: self.backoff,
+            max_retries: self.max_retries,
+        };
+        options.connection_lifetime = Duration::from_secs(self.connection_lifetime);
+        if let Some(path) = self.connection_cache {
+            options.connection_cache = Some(Arc::new(ConnectionCache::load(path)?));
+        }
+        options.proxy = self.proxy;
+        options.url_data = !self.no_url_data;
+        Ok(())
+    }
+}
+
+/// A positional command-line argument
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum InputArg {
+    Tracker(Tracker),
+    Target(TargetArg),
+}
+
+impl std::str::FromStr for InputArg {
+    type Err = InputArgError;
+
+    fn from_str(s: &str) -> Result<InputArg, InputArgError> {
+        match Url::parse(s) {
+            // Single-letter "schemes" are actually Windows drive letters.
+            Ok(url) if url.scheme().len() > 1 && url.scheme() != "magnet" => {
+                Ok(InputArg::Tracker(s.parse::<Tracker>()?))
+            }
+            _ => Ok(InputArg::Target(s.parse::<TargetArg>()?)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+enum InputArgError {
+    #[error(transparent)]
+    Tracker(#[from] TrackerUrlError),
+    #[error(transparent)]
+    Target(#[from] TargetArgError),
+}
+
+/// Exit status used when some, but not all, scrapes failed
+const PARTIAL_FAILURE_EXIT: u8 = 3;
+
+/// The error reported for a torrent that has no trackers to scrape it at
+const NO_TRACKERS_MESSAGE: &str = "no tracker URL given and no trackers known";
+
 fn main() -> ExitCode {
-    let args = Arguments::parse();
-    match run(args) {
-        Ok(()) => ExitCode::SUCCESS,
+    let mut args = Arguments::parse();
+    let r = match args.command.take() {
+        Some(Command::Announce(aargs)) => announce(aargs),
+        None => run(args),
+    };
+    match r {
+        Ok(tally) => tally.exit_code(),
         Err(e) => {
             if let Some(ioerr) = e.downcast_ref::<io::Error>()
                 && ioerr.kind() == ErrorKind::BrokenPipe
@@ -55,105 +451,716 @@ fn main() -> ExitCode {
     }
 }
 
+/// Print a warning message to stderr
+pub(crate) fn warn<D: fmt::Display>(msg: D) {
+    let _ = writeln!(stderr().lock(), "trscrape: warning: {msg}");
+}
+
 #[tokio::main(flavor = "current_thread")]
-async fn run(args: Arguments) -> anyhow::Result<()> {
+async fn run(args: Arguments) -> anyhow::Result<Tally> {
     let Arguments {
+        aggregate,
+        inputs,
+        dht,
+        dht_bootstrap,
+        fallback,
+        hash_file,
+        http_batch_size,
+        jobs,
+        max_url_length,
+        tracker_file,
+        udp_batch_size,
+        ws_batch_size,
+        common,
+        http,
+        udp,
+        ..
+    } = args;
+    let timeout = common.timeout();
+    let family = common.family();
+    let mut options = TrackerOptions {
+        http: HttpOptions {
+            batch_size: http_batch_size,
+            max_url_len: max_url_length,
+            timeout,
+            family,
+            ..HttpOptions::default()
+        },
+        udp: UdpOptions {
+            batch_size: udp_batch_size,
+            timeout,
+            family,
+            ..UdpOptions::default()
+        },
+        ws: WsOptions {
+            batch_size: ws_batch_size,
+            timeout,
+        },
+        dht: DhtOptions {
+            timeout,
+            ..DhtOptions::default()
+        },
+        fallback,
+    };
+    if !dht_bootstrap.is_empty() {
+        options.dht.bootstrap = dht_bootstrap;
+    }
+    http.apply(&mut options.http)?;
+    udp.apply(&mut options.udp)?;
+    let mut trackers = Vec::new();
+    let mut target_args = Vec::new();
+    for arg in inputs {
+        match arg {
+            InputArg::Tracker(tr) => trackers.push(tr),
+            InputArg::Target(t) => target_args.push(t),
+        }
+    }
+    for file in &tracker_file {
+        trackers.extend(read_list_file::<Tracker>(file)?);
+    }
+    for file in &hash_file {
+        target_args.extend(read_list_file::<TargetArg>(file)?);
+    }
+    if target_args.is_empty() {
+        return Ok(Tally::default());
+    }
+    let mut targets = Vec::new();
+    for t in target_args {
+        targets.extend(t.resolve()?);
+    }
+    let mut plan = plan_scrapes(&targets, trackers, dht);
+    if dht {
+        plan.groups
+            .push((Tracker::Dht(DhtTracker), targets.iter().collect()));
+    }
+    if common.trace {
+        init_tracing()?;
+    }
+    let mut printer = common.printer();
+    let options = &options;
+    // `buffered()` yields results in the same order as the plan, so the
+    // output is grouped by tracker regardless of which finishes first.
+    let mut reports = stream::iter(plan.groups)
+        .map(|(tracker, group)| async move {
+            let hashes = group.iter().map(|t| t.info_hash).collect::<Vec<_>>();
+            let report = tracker.scrape(&hashes, options).await;
+            (tracker, group, report)
+        })
+        .buffered(jobs.get());
+    let mut tally = Tally::default();
+    let mut per_hash: HashMap<InfoHash, Vec<TrackerScrape>> = HashMap::new();
+    while let Some((tracker, group, report)) = reports.next().await {
+        for t in group {
+            let result = report.get(t.info_hash);
+            if aggregate.is_some() {
+                let entry = per_hash.entry(t.info_hash).or_default();
+                if !entry.iter().any(|ts| ts.tracker == tracker) {
+                    tally.add(&result);
+                    entry.push(TrackerScrape {
+                        tracker: tracker.clone(),
+                        result,
+                    });
+                }
+            } else {
+                tally.add(&result);
+                printer.print(&tracker, t, &result)?;
+            }
+        }
+        for msg in report.warnings() {
+            warn(format_args!("{tracker}: {msg}"));
+        }
+    }
+    for t in plan.untracked {
+        tally.failed += 1;
+        if aggregate.is_none() {
+            printer.print_untracked(t)?;
+        }
+    }
+    if let Some(agg) = aggregate {
+        for t in &targets {
+            let results = per_hash.get(&t.info_hash).map_or(&[][..], Vec::as_slice);
+            printer.print_aggregate(t, agg, results)?;
+        }
+    }
+    save_connection_cache(&options.udp);
+    Ok(tally)
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn announce(args: AnnounceArgs) -> anyhow::Result<Tally> {
+    let AnnounceArgs {
+        downloaded,
+        event,
+        left,
+        numwant,
+        port,
+        uploaded,
+        common,
+        http,
+        udp,
         tracker,
-        hashes,
-        timeout,
-        trace,
-        json,
+        targets: target_args,
     } = args;
-    if !hashes.is_empty() {
-        if trace {
-            let timer = OffsetTime::local_rfc_3339()
-                .context("failed to determine local timezone offset")?;
-            tracing_subscriber::registry()
-                .with(
-                    tracing_subscriber::fmt::layer()
-                        .with_timer(timer)
-                        .with_ansi(stderr().is_terminal())
-                        .with_writer(stderr),
-                )
-                .with(
-                    Targets::new()
-                        .with_target(env!("CARGO_CRATE_NAME"), Level::TRACE)
-                        .with_target("reqwest", Level::TRACE)
-                        .with_target("tower_http", Level::TRACE)
-                        .with_default(Level::INFO),
-                )
-                .init();
-        }
-        match tokio::time::timeout(Duration::from_secs(timeout), tracker.scrape(&hashes)).await {
-            Ok(Ok(mut scrapemap)) => {
-                let mut printer = if json {
-                    Printer::json()
-                } else {
-                    Printer::text()
-                };
-                for ih in hashes {
-                    printer.print(ih, scrapemap.remove(&ih))?;
+    let mut targets = Vec::new();
+    for t in target_args {
+        targets.extend(t.resolve()?);
+    }
+    if common.trace {
+        init_tracing()?;
+    }
+    let params = AnnounceParams {
+        peer_id: AnnounceParams::random_peer_id(),
+        key: rand::random(),
+        port,
+        uploaded,
+        downloaded,
+        left,
+        event,
+        numwant,
+    };
+    let hashes = targets.iter().map(|t| t.info_hash).collect::<Vec<_>>();
+    let timeout = common.timeout();
+    let family = common.family();
+    let mut options = TrackerOptions {
+        http: HttpOptions {
+            timeout,
+            family,
+            ..HttpOptions::default()
+        },
+        udp: UdpOptions {
+            timeout,
+            family,
+            ..UdpOptions::default()
+        },
+        ws: WsOptions {
+            timeout,
+            ..WsOptions::default()
+        },
+        ..TrackerOptions::default()
+    };
+    http.apply(&mut options.http)?;
+    udp.apply(&mut options.udp)?;
+    let results = tracker.announce(&hashes, &params, &options).await;
+    let mut printer = common.printer();
+    let mut tally = Tally::default();
+    for (t, result) in std::iter::zip(&targets, results) {
+        tally.add(&result);
+        printer.print_announcement(&tracker, t, &result)?;
+    }
+    save_connection_cache(&options.udp);
+    Ok(tally)
+}
+
+/// Write out the UDP connection ID cache, if one is in use.  As the cache is
+/// only an optimization, failure to write it is merely warned about.
+fn save_connection_cache(options: &UdpOptions) {
+    if let Some(cache) = &options.connection_cache
+        && let Err(e) = cache.save()
+    {
+        warn(format_args!("{:#}", anyhow::Error::new(e)));
+    }
+}
+
+fn init_tracing() -> anyhow::Result<()> {
+    let timer =
+        OffsetTime::local_rfc_3339().context("failed to determine local timezone offset")?;
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_timer(timer)
+                .with_ansi(stderr().is_terminal())
+                .with_writer(stderr),
+        )
+        .with(
+            Targets::new()
+                .with_target(env!("CARGO_CRATE_NAME"), Level::TRACE)
+                .with_target("reqwest", Level::TRACE)
+                .with_target("tower_http", Level::TRACE)
+                .with_default(Level::INFO),
+        )
+        .init();
+    Ok(())
+}
+
+/// Counts of successful & failed scrapes or announcements of individual info
+/// hashes
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+struct Tally {
+    succeeded: usize,
+    failed: usize,
+}
+
+impl Tally {
+    fn add<T>(&mut self, result: &Result<T, Arc<TrackerError>>) {
+        if result.is_ok() {
+            self.succeeded += 1;
+        } else {
+            self.failed += 1;
+        }
+    }
+
+    fn exit_code(self) -> ExitCode {
+        if self.failed == 0 {
+            ExitCode::SUCCESS
+        } else if self.succeeded == 0 {
+            ExitCode::FAILURE
+        } else {
+            ExitCode::from(PARTIAL_FAILURE_EXIT)
+        }
+    }
+}
+
+/// The trackers to scrape and the targets to scrape at each one
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+struct ScrapePlan<'a> {
+    groups: Vec<(Tracker, Vec<&'a Target>)>,
+    /// Targets with no trackers to scrape them at, each of which counts as a
+    /// failure
+    untracked: Vec<&'a Target>,
+}
+
+/// Group the given targets by tracker: every target is scraped at each of the
+/// explicitly given `trackers`, followed by the trackers listed in its own
+/// metainfo, with the trackers kept in the order in which they were first
+/// encountered.  Targets without any trackers are set aside as untracked
+/// unless they will be looked up in the DHT.
+fn plan_scrapes(targets: &[Target], trackers: Vec<Tracker>, dht: bool) -> ScrapePlan<'_> {
+    let mut plan = ScrapePlan::default();
+    let explicit = !trackers.is_empty();
+    for tr in trackers {
+        if !plan.groups.iter().any(|(ptr, _)| ptr == &tr) {
+            plan.groups.push((tr, targets.iter().collect()));
+        }
+    }
+    for t in targets {
+        if !explicit && t.trackers.is_empty() && !dht {
+            plan.untracked.push(t);
+        }
+        for tr in &t.trackers {
+            if let Some((_, group)) = plan.groups.iter_mut().find(|(ptr, _)| ptr == tr) {
+                if !group.iter().any(|&gt| std::ptr::eq(gt, t)) {
+                    group.push(t);
                 }
-                Ok(())
+            } else {
+                plan.groups.push((tr.clone(), vec![t]));
             }
-            Ok(Err(e)) => Err(e.into()),
-            Err(_) => anyhow::bail!("tracker scrape action timed out"),
         }
-    } else {
-        Ok(())
     }
+    plan
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Printer {
+    style: PrintStyle,
+    base32: bool,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-enum Printer {
+enum PrintStyle {
     Text { first: bool },
     Json,
 }
 
 impl Printer {
-    fn text() -> Printer {
-        Printer::Text { first: true }
+    fn text(base32: bool) -> Printer {
+        Printer {
+            style: PrintStyle::Text { first: true },
+            base32,
+        }
     }
 
-    fn json() -> Printer {
-        Printer::Json
+    fn json(base32: bool) -> Printer {
+        Printer {
+            style: PrintStyle::Json,
+            base32,
+        }
     }
 
-    fn print(&mut self, info_hash: InfoHash, scrape: Option<Scrape>) -> io::Result<()> {
+    fn format_hash(&self, info_hash: InfoHash) -> String {
+        if self.base32 {
+            info_hash.to_base32()
+        } else {
+            info_hash.to_string()
+        }
+    }
+
+    fn print(
+        &mut self,
+        tracker: &Tracker,
+        target: &Target,
+        result: &ScrapeResult,
+    ) -> io::Result<()> {
         let mut out = stdout().lock();
-        match self {
-            Printer::Text { first } => {
+        let info_hash = self.format_hash(target.info_hash);
+        match &mut self.style {
+            PrintStyle::Text { first } => {
                 if !std::mem::replace(first, false) {
                     writeln!(&mut out)?;
                 }
-                if let Some(s) = scrape {
-                    writeln!(&mut out, "{info_hash}:")?;
-                    writeln!(&mut out, "  Complete/Seeders: {}", s.complete)?;
-                    writeln!(&mut out, "  Incomplete/Leechers: {}", s.incomplete)?;
-                    writeln!(&mut out, "  Downloaded: {}", s.downloaded)?;
-                } else {
-                    writeln!(&mut out, "{info_hash}: --- not tracked ---")?;
+                write_header(&mut out, &info_hash, target)?;
+                writeln!(&mut out, "  Tracker: {tracker}")?;
+                match result {
+                    Ok(scrape) => write_stats(&mut out, scrape.as_ref()),
+                    Err(e) => writeln!(&mut out, "  Error: {}", error_chain(e)),
+                }
+            }
+            PrintStyle::Json => {
+                let record = JsonRecord {
+                    info_hash,
+                    name: target.name.as_deref(),
+                    result: JsonResult::new(tracker, result),
+                };
+                serde_json::to_writer(&mut out, &record)?;
+                writeln!(&mut out)
+            }
+        }
+    }
+
+    /// Print the failure for a target that has no trackers to scrape it at
+    fn print_untracked(&mut self, target: &Target) -> io::Result<()> {
+        let mut out = stdout().lock();
+        let info_hash = self.format_hash(target.info_hash);
+        match &mut self.style {
+            PrintStyle::Text { first } => {
+                if !std::mem::replace(first, false) {
+                    writeln!(&mut out)?;
                 }
-                Ok(())
+                write_header(&mut out, &info_hash, target)?;
+                writeln!(&mut out, "  Error: {NO_TRACKERS_MESSAGE}")
+            }
+            PrintStyle::Json => {
+                let record = JsonRecord {
+                    info_hash,
+                    name: target.name.as_deref(),
+                    result: JsonResult {
+                        tracker: None,
+                        scrape: None,
+                        error: Some(JsonError {
+                            kind: "no-trackers",
+                            message: NO_TRACKERS_MESSAGE.into(),
+                        }),
+                    },
+                };
+                serde_json::to_writer(&mut out, &record)?;
+                writeln!(&mut out)
             }
-            Printer::Json => {
-                if let Some(Scrape {
-                    complete,
-                    incomplete,
-                    downloaded,
-                }) = scrape
-                {
-                    writeln!(
-                        &mut out,
-                        r#"{{"info_hash": "{info_hash}", "scrape": {{"complete": {complete}, "incomplete": {incomplete}, "downloaded": {downloaded}}}}}"#
-                    )
+        }
+    }
+
+    fn print_aggregate(
+        &mut self,
+        target: &Target,
+        aggregate: Aggregate,
+        results: &[TrackerScrape],
+    ) -> io::Result<()> {
+        let mut out = stdout().lock();
+        let info_hash = self.format_hash(target.info_hash);
+        let responded = results.iter().filter(|ts| ts.result.is_ok()).count();
+        let scrape = aggregate.combine(
+            results
+                .iter()
+                .filter_map(|ts| ts.result.as_ref().ok().cloned().flatten()),
+        );
+        match &mut self.style {
+            PrintStyle::Text { first } => {
+                if !std::mem::replace(first, false) {
+                    writeln!(&mut out)?;
+                }
+                write_header(&mut out, &info_hash, target)?;
+                writeln!(
+                    &mut out,
+                    "  Trackers: {responded} of {} responded",
+                    results.len()
+                )?;
+                for ts in results {
+                    if let Err(ref e) = ts.result {
+                        writeln!(&mut out, "  Error from {}: {}", ts.tracker, error_chain(e))?;
+                    }
+                }
+                if scrape.is_none() && responded == 0 {
+                    writeln!(&mut out, "  --- no trackers responded ---")
                 } else {
-                    writeln!(
-                        &mut out,
-                        r#"{{"info_hash": "{info_hash}", "scrape": null}}"#
-                    )
+                    write_stats(&mut out, scrape.as_ref())
                 }
             }
+            PrintStyle::Json => {
+                let record = JsonAggregateRecord {
+                    info_hash,
+                    name: target.name.as_deref(),
+                    aggregate,
+                    trackers: results.len(),
+                    responded,
+                    scrape,
+                    per_tracker: results
+                        .iter()
+                        .map(|ts| JsonResult::new(&ts.tracker, &ts.result))
+                        .collect(),
+                };
+                serde_json::to_writer(&mut out, &record)?;
+                writeln!(&mut out)
+            }
+        }
+    }
+
+    fn print_announcement(
+        &mut self,
+        tracker: &Tracker,
+        target: &Target,
+        result: &AnnounceResult,
+    ) -> io::Result<()> {
+        let mut out = stdout().lock();
+        let info_hash = self.format_hash(target.info_hash);
+        match &mut self.style {
+            PrintStyle::Text { first } => {
+                if !std::mem::replace(first, false) {
+                    writeln!(&mut out)?;
+                }
+                write_header(&mut out, &info_hash, target)?;
+                writeln!(&mut out, "  Tracker: {tracker}")?;
+                match result {
+                    Ok(ann) => {
+                        if let Some(ref warning) = ann.warning {
+                            writeln!(&mut out, "  Warning: {warning}")?;
+                        }
+                        writeln!(&mut out, "  Interval: {}", ann.interval)?;
+                        if let Some(min_interval) = ann.min_interval {
+                            writeln!(&mut out, "  Min Interval: {min_interval}")?;
+                        }
+                        if let Some(ref tracker_id) = ann.tracker_id {
+                            writeln!(&mut out, "  Tracker ID: {tracker_id}")?;
+                        }
+                        if let Some(seeders) = ann.seeders {
+                            writeln!(&mut out, "  Seeders: {seeders}")?;
+                        }
+                        if let Some(leechers) = ann.leechers {
+                            writeln!(&mut out, "  Leechers: {leechers}")?;
+                        }
+                        writeln!(&mut out, "  Peers: {}", ann.peers.len())?;
+                        for peer in &ann.peers {
+                            writeln!(&mut out, "    {peer}")?;
+                        }
+                        Ok(())
+                    }
+                    Err(e) => writeln!(&mut out, "  Error: {}", error_chain(e)),
+                }
+            }
+            PrintStyle::Json => {
+                let record = JsonAnnounceRecord {
+                    info_hash,
+                    name: target.name.as_deref(),
+                    tracker,
+                    announcement: result.as_ref().ok(),
+                    error: result.as_ref().err().map(|e| JsonError::new(e)),
+                };
+                serde_json::to_writer(&mut out, &record)?;
+                writeln!(&mut out)
+            }
+        }
+    }
+}
+
+fn write_header<W: Write>(mut out: W, info_hash: &str, target: &Target) -> io::Result<()> {
+    write!(out, "{info_hash}")?;
+    if let Some(ref name) = target.name {
+        write!(out, " ({name})")?;
+    }
+    writeln!(out, ":")
+}
+
+fn write_stats<W: Write>(mut out: W, scrape: Option<&Scrape>) -> io::Result<()> {
+    if let Some(s) = scrape {
+        writeln!(out, "  Complete/Seeders: {}", stat(s.complete))?;
+        writeln!(out, "  Incomplete/Leechers: {}", stat(s.incomplete))?;
+        if let Some(downloaders) = s.downloaders {
+            writeln!(out, "  Downloaders: {downloaders}")?;
+        }
+        writeln!(out, "  Downloaded: {}", stat(s.downloaded))?;
+        if s.estimated {
+            writeln!(out, "  (estimated from the DHT)")?;
+        }
+        if let Some(ref name) = s.name {
+            writeln!(out, "  Name: {name}")?;
+        }
+        if let Some(min_interval) = s.min_request_interval {
+            writeln!(out, "  Min Request Interval: {min_interval}")?;
         }
+    } else {
+        writeln!(out, "  --- not tracked ---")?;
+    }
+    Ok(())
+}
+
+/// Render a statistic for display, or "unknown" if it is not known
+fn stat(value: Option<u32>) -> String {
+    value.map_or_else(|| String::from("unknown"), |n| n.to_string())
+}
+
+/// Render an error and all of its sources as a single line
+fn error_chain(e: &TrackerError) -> String {
+    let mut s = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(src) = source {
+        s.push_str(": ");
+        s.push_str(&src.to_string());
+        source = src.source();
+    }
+    s
+}
+
+/// The result of scraping a single tracker for a single info hash
+#[derive(Clone, Debug)]
+struct TrackerScrape {
+    tracker: Tracker,
+    result: ScrapeResult,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+struct JsonRecord<'a> {
+    info_hash: String,
+    name: Option<&'a str>,
+    #[serde(flatten)]
+    result: JsonResult<'a>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+struct JsonResult<'a> {
+    /// `None` if the target has no trackers to scrape it at
+    tracker: Option<&'a Tracker>,
+    scrape: Option<Scrape>,
+    error: Option<JsonError>,
+}
+
+impl<'a> JsonResult<'a> {
+    fn new(tracker: &'a Tracker, result: &ScrapeResult) -> JsonResult<'a> {
+        let (scrape, error) = match result {
+            Ok(scrape) => (scrape.clone(), None),
+            Err(e) => (None, Some(JsonError::new(e))),
+        };
+        JsonResult {
+            tracker: Some(tracker),
+            scrape,
+            error,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+struct JsonError {
+    kind: &'static str,
+    message: String,
+}
+
+impl JsonError {
+    fn new(e: &TrackerError) -> JsonError {
+        JsonError {
+            kind: e.kind(),
+            message: error_chain(e),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+struct JsonAnnounceRecord<'a> {
+    info_hash: String,
+    name: Option<&'a str>,
+    tracker: &'a Tracker,
+    announcement: Option<&'a Announcement>,
+    error: Option<JsonError>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+struct JsonAggregateRecord<'a> {
+    info_hash: String,
+    name: Option<&'a str>,
+    aggregate: Aggregate,
+    trackers: usize,
+    responded: usize,
+    scrape: Option<Scrape>,
+    per_tracker: Vec<JsonResult<'a>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(info_hash: &str, trackers: &[&str]) -> Target {
+        Target {
+            info_hash: info_hash.parse::<InfoHash>().unwrap(),
+            name: None,
+            trackers: trackers
+                .iter()
+                .map(|s| s.parse::<Tracker>().unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn plan_scrapes_by_tracker() {
+        let targets = [
+            target(
+                "28c55196f57753c40aceb6fb58617e6995a7eddb",
+                &[
+                    "udp://tracker.example.com:6969",
+                    "http://a.example.com/announce",
+                ],
+            ),
+            target("da39a3ee5e6b4b0d3255bfef95601890afd80709", &[]),
+            target(
+                "b851474b74f65cd19f981c723590e3e520242b97",
+                &["http://a.example.com/announce"],
+            ),
+        ];
+        let plan = plan_scrapes(&targets, Vec::new(), false);
+        assert_eq!(
+            plan.groups,
+            [
+                (targets[0].trackers[0].clone(), vec![&targets[0]],),
+                (
+                    targets[0].trackers[1].clone(),
+                    vec![&targets[0], &targets[2]],
+                ),
+            ]
+        );
+        assert_eq!(plan.untracked, [&targets[1]]);
+        let plan = plan_scrapes(&targets, Vec::new(), true);
+        assert_eq!(plan.groups.len(), 2);
+        assert!(plan.untracked.is_empty());
+    }
+
+    #[test]
+    fn plan_scrapes_with_given_trackers() {
+        let targets = [
+            target(
+                "28c55196f57753c40aceb6fb58617e6995a7eddb",
+                &[
+                    "udp://tracker.example.com:6969",
+                    "http://a.example.com/announce",
+                ],
+            ),
+            target("da39a3ee5e6b4b0d3255bfef95601890afd80709", &[]),
+            target(
+                "b851474b74f65cd19f981c723590e3e520242b97",
+                &["http://b.example.com/announce"],
+            ),
+        ];
+        let given = ["http://a.example.com/announce", "udp://c.example.com:1337"]
+            .map(|s| s.parse::<Tracker>().unwrap());
+        let plan = plan_scrapes(&targets, given.to_vec(), false);
+        assert_eq!(
+            plan.groups,
+            [
+                (
+                    given[0].clone(),
+                    vec![&targets[0], &targets[1], &targets[2]]
+                ),
+                (
+                    given[1].clone(),
+                    vec![&targets[0], &targets[1], &targets[2]]
+                ),
+                (targets[0].trackers[0].clone(), vec![&targets[0]]),
+                (targets[2].trackers[0].clone(), vec![&targets[2]]),
+            ]
+        );
+        assert!(plan.untracked.is_empty());
     }
 }
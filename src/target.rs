@@ -0,0 +1,222 @@
+use crate::infohash::{InfoHash, InfoHashError};
+use crate::magnet::{Magnet, MagnetError};
+use crate::torrent::{Torrent, TorrentError};
+use crate::tracker::Tracker;
+use crate::warn;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// A torrent to scrape for, as specified on the command line
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum TargetArg {
+    InfoHash(InfoHash),
+    Magnet(Magnet),
+    Torrent(PathBuf),
+}
+
+impl TargetArg {
+    /// Resolve the argument into the torrents to scrape for.  Hybrid v1/v2
+    /// torrents produce one target per info hash.
+    pub(crate) fn resolve(self) -> Result<Vec<Target>, TorrentError> {
+        let (info_hashes, name, trackers) = match self {
+            TargetArg::InfoHash(info_hash) => (vec![info_hash], None, Vec::new()),
+            TargetArg::Magnet(Magnet {
+                info_hashes,
+                name,
+                trackers,
+            }) => {
+                let trackers =
+                    parse_trackers(trackers, format_args!("magnet link for {}", info_hashes[0]));
+                (info_hashes, name, trackers)
+            }
+            TargetArg::Torrent(path) => {
+                let Torrent {
+                    info_hashes,
+                    name,
+                    trackers,
+                } = Torrent::from_file(&path)?;
+                let trackers = parse_trackers(trackers, path.display());
+                (info_hashes, name, trackers)
+            }
+        };
+        Ok(info_hashes
+            .into_iter()
+            .map(|info_hash| Target {
+                info_hash,
+                name: name.clone(),
+                trackers: trackers.clone(),
+            })
+            .collect())
+    }
+}
+
+impl std::str::FromStr for TargetArg {
+    type Err = TargetArgError;
+
+    fn from_str(s: &str) -> Result<TargetArg, TargetArgError> {
+        if s.starts_with("magnet:") {
+            return Ok(TargetArg::Magnet(s.parse::<Magnet>()?));
+        }
+        match s.parse::<InfoHash>() {
+            Ok(ih) => Ok(TargetArg::InfoHash(ih)),
+            // A string that is not an info hash is only treated as a path if
+            // it exists or looks like one, so that typos in info hashes are
+            // reported as such.
+            Err(e) if !looks_like_path(s) && !Path::new(s).exists() => Err(e.into()),
+            Err(_) => Ok(TargetArg::Torrent(PathBuf::from(s))),
+        }
+    }
+}
+
+/// Test whether a string that is not an info hash is evidently meant as a
+/// path to a .torrent file
+fn looks_like_path(s: &str) -> bool {
+    s.contains(std::path::MAIN_SEPARATOR) || s.contains('/') || s.ends_with(".torrent")
+}
+
+#[derive(Clone, Debug, Error, Eq, PartialEq)]
+pub(crate) enum TargetArgError {
+    #[error(transparent)]
+    InfoHash(#[from] InfoHashError),
+    #[error(transparent)]
+    Magnet(#[from] MagnetError),
+}
+
+/// Parse a list of tracker URLs, warning about & discarding any that are not
+/// supported
+fn parse_trackers<D: fmt::Display>(urls: Vec<String>, source: D) -> Vec<Tracker> {
+    urls.into_iter()
+        .filter_map(|url| match url.parse::<Tracker>() {
+            Ok(tr) => Some(tr),
+            Err(e) => {
+                warn(format_args!(
+                    "skipping tracker {url:?} in {source}: {:#}",
+                    anyhow::Error::from(e)
+                ));
+                None
+            }
+        })
+        .collect()
+}
+
+/// A torrent to scrape for
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Target {
+    pub(crate) info_hash: InfoHash,
+    /// The torrent's name, if known
+    pub(crate) name: Option<String>,
+    /// The trackers listed in the torrent's metainfo, if known
+    pub(crate) trackers: Vec<Tracker>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{ListFileError, read_list};
+    use patharg::InputArg;
+
+    #[test]
+    fn parse_info_hash_arg() {
+        assert_eq!(
+            "28C55196F57753C40ACEB6FB58617E6995A7EDDB"
+                .parse::<TargetArg>()
+                .unwrap(),
+            TargetArg::InfoHash(
+                "28c55196f57753c40aceb6fb58617e6995a7eddb"
+                    .parse::<InfoHash>()
+                    .unwrap()
+            )
+        );
+    }
+
+    #[test]
+    fn parse_magnet_arg() {
+        assert_eq!(
+            "magnet:?xt=urn:btih:28C55196F57753C40ACEB6FB58617E6995A7EDDB"
+                .parse::<TargetArg>()
+                .unwrap(),
+            TargetArg::Magnet(Magnet {
+                info_hashes: vec![
+                    "28c55196f57753c40aceb6fb58617e6995a7eddb"
+                        .parse::<InfoHash>()
+                        .unwrap()
+                ],
+                name: None,
+                trackers: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn parse_bad_info_hash_arg() {
+        assert!(matches!(
+            "28C55196F57753C40ACEB6FB58617E6995A7EDDX".parse::<TargetArg>(),
+            Err(TargetArgError::InfoHash(InfoHashError::InvalidHex(_)))
+        ));
+    }
+
+    #[test]
+    fn parse_mistyped_info_hash_arg() {
+        assert!(matches!(
+            "28C55196F57753C40ACEB6FB58617E6995A7EDD".parse::<TargetArg>(),
+            Err(TargetArgError::InfoHash(
+                InfoHashError::InvalidStringLength(39)
+            ))
+        ));
+        assert!(matches!(
+            "1221caf1e1c30e81cb361b9ee167c4aa64228a7fa4fa9f6105232b28ad099f3a302e"
+                .parse::<TargetArg>(),
+            Err(TargetArgError::InfoHash(InfoHashError::InvalidMultihash))
+        ));
+    }
+
+    #[test]
+    fn read_target_lines() {
+        let text = concat!(
+            "# Some torrents\n",
+            "28C55196F57753C40ACEB6FB58617E6995A7EDDB\n",
+            "\n",
+            "  magnet:?xt=urn:btih:FDCVDFXVO5J4ICWOW35VQYL6NGK2P3O3  \n",
+            "foo/bar.torrent\n",
+        );
+        let targets = read_list::<TargetArg, _>(&InputArg::Stdin, text.as_bytes()).unwrap();
+        let info_hash = "28c55196f57753c40aceb6fb58617e6995a7eddb"
+            .parse::<InfoHash>()
+            .unwrap();
+        assert_eq!(
+            targets,
+            [
+                TargetArg::InfoHash(info_hash),
+                TargetArg::Magnet(Magnet {
+                    info_hashes: vec![info_hash],
+                    name: None,
+                    trackers: Vec::new(),
+                }),
+                TargetArg::Torrent(PathBuf::from("foo/bar.torrent")),
+            ]
+        );
+    }
+
+    #[test]
+    fn read_target_bad_line() {
+        let text = "# Some torrents\n28C55196F57753C40ACEB6FB58617E6995A7EDDB\n28C55196F57753C40ACEB6FB58617E6995A7EDDX\n";
+        let r = read_list::<TargetArg, _>(&InputArg::Stdin, text.as_bytes());
+        assert!(matches!(
+            r,
+            Err(ListFileError::Parse {
+                lineno: 3,
+                source: TargetArgError::InfoHash(InfoHashError::InvalidHex(_)),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn parse_torrent_arg() {
+        assert_eq!(
+            "foo/bar.torrent".parse::<TargetArg>().unwrap(),
+            TargetArg::Torrent(PathBuf::from("foo/bar.torrent"))
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use crate::infohash::InfoHash;
+use crate::util::{UnbencodeError, decode_bencode};
+use bendy::decoding::{Error as BendyError, FromBencode, Object, ResultExt};
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// The parts of a torrent's metainfo that are relevant to scraping
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Torrent {
+    /// The torrent's info hashes: the v1 hash for v1 torrents, the v2 hash for
+    /// v2 torrents, or both (v1 first) for hybrid torrents
+    pub(crate) info_hashes: Vec<InfoHash>,
+    pub(crate) name: Option<String>,
+    /// Tracker URLs from the `announce-list` (BEP 12) or, if that is absent,
+    /// `announce` field, in tier order
+    pub(crate) trackers: Vec<String>,
+}
+
+impl Torrent {
+    pub(crate) fn from_file(path: &Path) -> Result<Torrent, TorrentError> {
+        let buf = std::fs::read(path).map_err(|source| TorrentError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        decode_bencode::<Torrent>(&buf).map_err(|source| TorrentError::Parse {
+            path: path.to_owned(),
+            source,
+        })
+    }
+}
+
+impl FromBencode for Torrent {
+    fn decode_bencode_object(object: Object<'_, '_>) -> Result<Self, BendyError> {
+        let mut info = None;
+        let mut announce = None;
+        let mut announce_list = None;
+        let mut dd = object.try_into_dictionary()?;
+        while let Some(kv) = dd.next_pair()? {
+            match kv {
+                (b"info", val) => {
+                    let mut name = None;
+                    let mut meta_version = None;
+                    let mut has_pieces = false;
+                    let mut idict = val.try_into_dictionary().context("info")?;
+                    while let Some(kv) = idict.next_pair().context("info")? {
+                        match kv {
+                            (b"name", val) => {
+                                name = Some(decode_string(val).context("info.name")?);
+                            }
+                            (b"meta version", val) => {
+                                meta_version = Some(
+                                    u32::decode_bencode_object(val).context("info.meta version")?,
+                                );
+                            }
+                            (b"pieces", _) => has_pieces = true,
+                            _ => (),
+                        }
+                    }
+                    // The info hashes are computed over the exact bytes of
+                    // the info dictionary as they appear in the file, not
+                    // over a re-encoding.
+                    let raw = idict.into_raw().context("info")?;
+                    let mut info_hashes = Vec::new();
+                    // Hybrid torrents (BEP 52) have both a "meta version" of 2
+                    // and v1 "pieces".
+                    if meta_version != Some(2) || has_pieces {
+                        info_hashes.push(InfoHash::V1(Sha1::digest(raw).into()));
+                    }
+                    if meta_version == Some(2) {
+                        info_hashes.push(InfoHash::V2(Sha256::digest(raw).into()));
+                    }
+                    info = Some((info_hashes, name));
+                }
+                (b"announce", val) => {
+                    announce = Some(decode_string(val).context("announce")?);
+                }
+                (b"announce-list", val) => {
+                    let mut tiers = Vec::new();
+                    let mut tlist = val.try_into_list().context("announce-list")?;
+                    while let Some(tier) = tlist.next_object().context("announce-list")? {
+                        let mut ulist = tier.try_into_list().context("announce-list.<tier>")?;
+                        while let Some(url) = ulist.next_object().context("announce-list.<tier>")? {
+                            tiers.push(decode_string(url).context("announce-list.<tier>.<url>")?);
+                        }
+                    }
+                    announce_list = Some(tiers);
+                }
+                _ => (),
+            }
+        }
+        let (info_hashes, name) = info.ok_or_else(|| BendyError::missing_field("info"))?;
+        // Per BEP 12, `announce` is ignored when `announce-list` is present.
+        let mut trackers = match announce_list {
+            Some(tiers) if !tiers.is_empty() => tiers,
+            _ => announce.into_iter().collect(),
+        };
+        let mut seen = HashSet::new();
+        trackers.retain(|url| seen.insert(url.clone()));
+        Ok(Torrent {
+            info_hashes,
+            name,
+            trackers,
+        })
+    }
+}
+
+fn decode_string(object: Object<'_, '_>) -> Result<String, BendyError> {
+    Ok(String::from_utf8_lossy(object.try_into_bytes()?).into_owned())
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum TorrentError {
+    #[error("failed to read torrent file {}", path.display())]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse torrent file {}", path.display())]
+    Parse {
+        path: PathBuf,
+        source: UnbencodeError,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_torrent() {
+        let buf = b"d8:announce35:http://tracker.example.com/announce4:infod6:lengthi12345e4:name8:test.txt12:piece lengthi16384e6:pieces20:\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\x10\x11\x12\x13ee";
+        let torrent = decode_bencode::<Torrent>(buf).unwrap();
+        assert_eq!(torrent.name.as_deref(), Some("test.txt"));
+        assert_eq!(
+            torrent.info_hashes,
+            ["086109a56358a4eb0476d5307c42ee87c2f2be74"
+                .parse::<InfoHash>()
+                .unwrap()]
+        );
+        assert_eq!(torrent.trackers, ["http://tracker.example.com/announce"]);
+    }
+
+    #[test]
+    fn parse_torrent_announce_list() {
+        let buf = b"d8:announce35:http://tracker.example.com/announce13:announce-listll35:http://tracker.example.com/announce30:udp://tracker.example.net:6969el31:https://tracker.example.org/annee4:infod6:lengthi12345e4:name8:test.txt12:piece lengthi16384e6:pieces20:\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\x10\x11\x12\x13ee";
+        let torrent = decode_bencode::<Torrent>(buf).unwrap();
+        assert_eq!(
+            torrent.trackers,
+            [
+                "http://tracker.example.com/announce",
+                "udp://tracker.example.net:6969",
+                "https://tracker.example.org/ann",
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_v2_torrent() {
+        let buf = b"d4:infod9:file treed8:test.txtd0:d6:lengthi12345e11:pieces root32:\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00eee12:meta versioni2e4:name8:test.txt12:piece lengthi16384eee";
+        let torrent = decode_bencode::<Torrent>(buf).unwrap();
+        assert_eq!(
+            torrent.info_hashes,
+            [
+                "5dc512431c5dfdce6819fb48603edbce270ba3b142c622909fb638f0f7348f83"
+                    .parse::<InfoHash>()
+                    .unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_hybrid_torrent() {
+        let buf = b"d4:infod9:file treed8:test.txtd0:d6:lengthi12345e11:pieces root32:\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00eee6:lengthi12345e12:meta versioni2e4:name8:test.txt12:piece lengthi16384e6:pieces20:\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a\x0b\x0c\x0d\x0e\x0f\x10\x11\x12\x13ee";
+        let torrent = decode_bencode::<Torrent>(buf).unwrap();
+        assert_eq!(
+            torrent.info_hashes,
+            [
+                "451645cfc59c57f35f59b0604f5fe210c8ccb7d7"
+                    .parse::<InfoHash>()
+                    .unwrap(),
+                "f5f1b7426bcc3548624fc786dbf287b6df0bf8bf55607558e0562bef19369aac"
+                    .parse::<InfoHash>()
+                    .unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_torrent_no_info() {
+        let buf = b"d8:announce35:http://tracker.example.com/announcee";
+        assert!(decode_bencode::<Torrent>(buf).is_err());
+    }
+}
@@ -0,0 +1,185 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tempfile::NamedTempFile;
+use thiserror::Error;
+use tokio::time::Instant;
+
+/// An on-disk cache of the connection IDs issued by UDP trackers, keyed by
+/// tracker address, so that successive runs made within a connection ID's
+/// lifetime can skip the BEP 15 connection handshake
+#[derive(Debug)]
+pub(crate) struct ConnectionCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<SocketAddr, CacheEntry>>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct CacheEntry {
+    connection_id: u64,
+    /// When the connection ID expires, in seconds since the UNIX epoch
+    expires: u64,
+}
+
+impl ConnectionCache {
+    /// Load the cache stored at the given path.  If the file does not exist,
+    /// the cache starts out empty.
+    pub(crate) fn load(path: PathBuf) -> Result<ConnectionCache, CacheError> {
+        let entries = match fs::read(&path) {
+            Ok(src) => serde_json::from_slice::<Vec<(SocketAddr, CacheEntry)>>(&src)
+                .map_err(|source| CacheError::Parse {
+                    path: path.clone(),
+                    source,
+                })?
+                .into_iter()
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(source) => return Err(CacheError::Read { path, source }),
+        };
+        Ok(ConnectionCache {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Write the unexpired entries in the cache back to its file.  The file is
+    /// replaced atomically so that concurrent runs never see a partial cache.
+    pub(crate) fn save(&self) -> Result<(), CacheError> {
+        let now = unix_now();
+        let entries = self
+            .lock()
+            .iter()
+            .filter(|(_, entry)| entry.expires > now)
+            .map(|(&addr, &entry)| (addr, entry))
+            .collect::<Vec<_>>();
+        let src = serde_json::to_vec(&entries).expect("cache entries should serialize");
+        let parent = match self.path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        NamedTempFile::new_in(parent)
+            .and_then(|mut tmpfile| {
+                tmpfile.write_all(&src)?;
+                tmpfile.persist(&self.path)?;
+                Ok(())
+            })
+            .map_err(|source| CacheError::Write {
+                path: self.path.clone(),
+                source,
+            })
+    }
+
+    /// Return the cached connection ID for the tracker at the given address
+    /// and the time at which it expires, if there is one that has not yet
+    /// expired
+    pub(crate) fn get(&self, addr: SocketAddr) -> Option<(u64, Instant)> {
+        let entry = *self.lock().get(&addr)?;
+        let remaining = entry.expires.checked_sub(unix_now())?;
+        (remaining > 0).then(|| {
+            (
+                entry.connection_id,
+                Instant::now() + Duration::from_secs(remaining),
+            )
+        })
+    }
+
+    pub(crate) fn contains(&self, addr: SocketAddr) -> bool {
+        self.get(addr).is_some()
+    }
+
+    /// Record a connection ID obtained from the tracker at the given address
+    pub(crate) fn insert(&self, addr: SocketAddr, connection_id: u64, expiration: Instant) {
+        // Round the expiration time down so that it is never overestimated.
+        let remaining = expiration.saturating_duration_since(Instant::now());
+        let expires = unix_now().saturating_add(remaining.as_secs());
+        self.lock().insert(
+            addr,
+            CacheEntry {
+                connection_id,
+                expires,
+            },
+        );
+    }
+
+    /// Forget the connection ID for the tracker at the given address, e.g.,
+    /// because the tracker rejected it
+    pub(crate) fn remove(&self, addr: SocketAddr) {
+        self.lock().remove(&addr);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<SocketAddr, CacheEntry>> {
+        // The map is never left in an inconsistent state, so a poisoned lock
+        // can be used as-is.
+        self.entries
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum CacheError {
+    #[error("failed to read connection cache {}", .path.display())]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to parse connection cache {}", .path.display())]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[error("failed to write connection cache {}", .path.display())]
+    Write { path: PathBuf, source: io::Error },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("connections.json");
+        let addr1 = "192.0.2.1:6969".parse::<SocketAddr>().unwrap();
+        let addr2 = "[2001:db8::1]:1337".parse::<SocketAddr>().unwrap();
+        let addr3 = "192.0.2.3:80".parse::<SocketAddr>().unwrap();
+        let cache = ConnectionCache::load(path.clone()).unwrap();
+        assert_eq!(cache.get(addr1), None);
+        cache.insert(
+            addr1,
+            0x0123456789ABCDEF,
+            Instant::now() + Duration::from_secs(60),
+        );
+        cache.insert(addr2, 42, Instant::now() + Duration::from_secs(60));
+        cache.insert(addr3, 23, Instant::now());
+        cache.save().unwrap();
+        let cache = ConnectionCache::load(path).unwrap();
+        let (id, expiration) = cache.get(addr1).unwrap();
+        assert_eq!(id, 0x0123456789ABCDEF);
+        assert!(expiration <= Instant::now() + Duration::from_secs(60));
+        assert!(expiration > Instant::now() + Duration::from_secs(50));
+        assert_eq!(cache.get(addr2).map(|(id, _)| id), Some(42));
+        assert!(!cache.contains(addr3));
+        cache.remove(addr2);
+        assert!(!cache.contains(addr2));
+    }
+
+    #[test]
+    fn load_invalid() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let path = tmpdir.path().join("connections.json");
+        fs::write(&path, "not json").unwrap();
+        assert!(matches!(
+            ConnectionCache::load(path),
+            Err(CacheError::Parse { .. })
+        ));
+    }
+}
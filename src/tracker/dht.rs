@@ -0,0 +1,804 @@
+use super::{
+    AnnounceParams, AnnounceResult, Scrape, ScrapeMap, ScrapeReport, TrackerError, with_timeout,
+};
+use crate::infohash::InfoHash;
+use crate::util::{PacketError, TryBytes, TryFromBuf, decode_bencode};
+use bendy::decoding::{Error as BendyError, FromBencode, Object, ResultExt};
+use bendy::encoding::Encoder;
+use bytes::{Buf, Bytes, BytesMut};
+use rand::random;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::{UdpSocket, lookup_host};
+use tokio::time::{Instant, timeout_at};
+
+/// Size of buffer for receiving incoming KRPC messages
+const UDP_PACKET_LEN: usize = 65535;
+
+/// Number of nodes queried at once during a lookup (the "alpha" of the
+/// Kademlia paper)
+const PARALLELISM: usize = 8;
+
+/// Number of closest nodes that must have been queried for a lookup to be
+/// considered converged (the "K" of BEP 5)
+const CLOSEST_NODES: usize = 8;
+
+/// Length of a BEP 33 bloom filter in bytes
+const BLOOM_FILTER_LEN: usize = 256;
+
+/// Length of a BEP 33 bloom filter in bits
+const BLOOM_FILTER_BITS: u32 = 2048;
+
+type NodeId = [u8; 20];
+
+/// The BitTorrent mainline DHT ([BEP 5]), queried for estimates of swarm
+/// sizes using the scrape extension of BEP 33.  Only IPv4 nodes are
+/// contacted.
+///
+/// [BEP 5]: https://www.bittorrent.org/beps/bep_0005.html
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct DhtTracker;
+
+/// Settings for looking up torrents in the DHT
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct DhtOptions {
+    /// Hosts & ports of the nodes from which to start lookups
+    pub(crate) bootstrap: Vec<String>,
+    /// Maximum amount of time to spend on each lookup.  When this runs out,
+    /// the estimate is made from the responses received so far.
+    pub(crate) timeout: Duration,
+    /// How long to wait for each node to reply to a query
+    pub(crate) query_timeout: Duration,
+    /// Maximum number of nodes to query during each lookup
+    pub(crate) max_queries: usize,
+}
+
+impl Default for DhtOptions {
+    fn default() -> DhtOptions {
+        DhtOptions {
+            bootstrap: vec![
+                "router.bittorrent.com:6881".into(),
+                "dht.transmissionbt.com:6881".into(),
+                "router.utorrent.com:6881".into(),
+            ],
+            timeout: Duration::from_secs(30),
+            query_timeout: Duration::from_secs(2),
+            max_queries: 200,
+        }
+    }
+}
+
+impl DhtTracker {
+    /// Look up each of the given info hashes in the DHT in turn and estimate
+    /// the sizes of their swarms
+    #[tracing::instrument(name = "scrape-dht", skip_all)]
+    pub(crate) async fn scrape(&self, hashes: &[InfoHash], options: &DhtOptions) -> ScrapeReport {
+        let mut report = ScrapeReport::default();
+        let client = match with_timeout(options.timeout, DhtClient::new(options)).await {
+            Ok(client) => client,
+            Err(e) => {
+                report.fail(hashes, e);
+                return report;
+            }
+        };
+        for &ih in hashes {
+            let r = client
+                .lookup(ih)
+                .await
+                .map(|est| est.map(|s| ScrapeMap::from([(ih, s)])).unwrap_or_default());
+            report.record(&[ih], r.map_err(TrackerError::from));
+        }
+        report
+    }
+
+    /// The DHT cannot be announced to without running a full node, so this
+    /// always fails.
+    pub(crate) fn announce(
+        &self,
+        hashes: &[InfoHash],
+        _params: &AnnounceParams,
+        _options: &DhtOptions,
+    ) -> Vec<AnnounceResult> {
+        let e = Arc::new(TrackerError::from(DhtError::AnnounceUnsupported));
+        hashes.iter().map(|_| Err(Arc::clone(&e))).collect()
+    }
+}
+
+impl fmt::Display for DhtTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "dht")
+    }
+}
+
+#[derive(Debug)]
+struct DhtClient {
+    socket: UdpSocket,
+    /// The node ID under which this client sends queries
+    id: NodeId,
+    bootstrap: Vec<SocketAddrV4>,
+    query_timeout: Duration,
+    lookup_timeout: Duration,
+    max_queries: usize,
+}
+
+impl DhtClient {
+    async fn new(options: &DhtOptions) -> Result<DhtClient, TrackerError> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+            .await
+            .map_err(DhtError::Bind)?;
+        let mut bootstrap = Vec::new();
+        for node in &options.bootstrap {
+            match lookup_host(node.as_str()).await {
+                Ok(addrs) => bootstrap.extend(addrs.filter_map(|addr| match addr {
+                    SocketAddr::V4(a) => Some(a),
+                    SocketAddr::V6(_) => None,
+                })),
+                Err(e) => tracing::info!(node, "Failed to resolve DHT bootstrap node: {e}"),
+            }
+        }
+        if bootstrap.is_empty() {
+            return Err(DhtError::NoBootstrap.into());
+        }
+        Ok(DhtClient {
+            socket,
+            id: random(),
+            bootstrap,
+            query_timeout: options.query_timeout,
+            lookup_timeout: options.timeout,
+            max_queries: options.max_queries,
+        })
+    }
+
+    /// Perform an iterative `get_peers` lookup for the given info hash with
+    /// the BEP 33 `scrape` flag set, merging the bloom filters returned by
+    /// all nodes.  Returns `None` if no node returned any bloom filters.
+    async fn lookup(&self, info_hash: InfoHash) -> Result<Option<Scrape>, DhtError> {
+        let target = NodeId::try_from(info_hash.tracker_bytes())
+            .expect("tracker bytes should be 20 bytes long");
+        tracing::info!(%info_hash, "Starting DHT lookup");
+        let deadline = Instant::now() + self.lookup_timeout;
+        let mut lookup = Lookup::new(target, &self.bootstrap);
+        let mut xid: u16 = random();
+        let mut queries = 0;
+        while queries < self.max_queries && Instant::now() < deadline {
+            let batch = lookup.next_batch(PARALLELISM.min(self.max_queries - queries));
+            if batch.is_empty() {
+                break;
+            }
+            let mut pending = HashMap::new();
+            for addr in batch {
+                xid = xid.wrapping_add(1);
+                let msg = encode_get_peers(&xid.to_be_bytes(), &self.id, &target);
+                self.socket
+                    .send_to(&msg, addr)
+                    .await
+                    .map_err(DhtError::Send)?;
+                pending.insert(xid.to_be_bytes(), addr);
+                queries += 1;
+            }
+            let round_deadline = deadline.min(Instant::now() + self.query_timeout);
+            while !pending.is_empty() {
+                let Ok(r) = timeout_at(round_deadline, self.recv()).await else {
+                    break;
+                };
+                let (buf, sender) = r?;
+                let msg = match decode_bencode::<KrpcMessage>(&buf) {
+                    Ok(msg) => msg,
+                    Err(e) => {
+                        tracing::info!(%sender, "Ignoring malformed DHT message: {e}");
+                        continue;
+                    }
+                };
+                let Some(xid) = <[u8; 2]>::try_from(msg.transaction_id.as_slice()).ok() else {
+                    continue;
+                };
+                if pending
+                    .get(&xid)
+                    .is_none_or(|&addr| SocketAddr::V4(addr) != sender)
+                {
+                    tracing::info!(%sender, "Ignoring unexpected DHT message");
+                    continue;
+                }
+                pending.remove(&xid);
+                match msg.body {
+                    KrpcBody::Response(resp) => lookup.record(*resp),
+                    KrpcBody::Error(msg) => {
+                        tracing::info!(%sender, "DHT node replied with error: {msg}");
+                    }
+                    KrpcBody::Other => (),
+                }
+            }
+        }
+        tracing::info!(
+            queries,
+            responses = lookup.responded.len(),
+            "Finished DHT lookup"
+        );
+        if lookup.responded.is_empty() {
+            return Err(DhtError::NoResponse);
+        }
+        Ok(lookup.estimate())
+    }
+
+    async fn recv(&self) -> Result<(Bytes, SocketAddr), DhtError> {
+        let mut buf = BytesMut::with_capacity(UDP_PACKET_LEN);
+        let (_, sender) = self
+            .socket
+            .recv_buf_from(&mut buf)
+            .await
+            .map_err(DhtError::Recv)?;
+        Ok((buf.freeze(), sender))
+    }
+}
+
+/// The state of an iterative lookup
+#[derive(Clone, Debug)]
+struct Lookup {
+    target: NodeId,
+    /// Nodes not yet queried whose IDs are unknown, i.e., bootstrap nodes
+    unknown: Vec<SocketAddrV4>,
+    /// Nodes not yet queried, sorted by distance from the target
+    candidates: Vec<(NodeId, SocketAddrV4)>,
+    queried: HashSet<SocketAddrV4>,
+    /// Distances from the target of the nodes that have replied, sorted
+    responded: Vec<NodeId>,
+    seeds: Option<BloomFilter>,
+    peers: Option<BloomFilter>,
+}
+
+impl Lookup {
+    fn new(target: NodeId, bootstrap: &[SocketAddrV4]) -> Lookup {
+        Lookup {
+            target,
+            unknown: bootstrap.to_vec(),
+            candidates: Vec::new(),
+            queried: HashSet::new(),
+            responded: Vec::new(),
+            seeds: None,
+            peers: None,
+        }
+    }
+
+    /// Select up to `qty` nodes to query next and mark them as queried.
+    /// Candidates no closer to the target than the closest nodes that have
+    /// already replied are not selected, as they cannot improve the lookup.
+    fn next_batch(&mut self, qty: usize) -> Vec<SocketAddrV4> {
+        let mut batch = Vec::with_capacity(qty);
+        while batch.len() < qty {
+            if let Some(addr) = self.unknown.pop() {
+                batch.push(addr);
+                continue;
+            }
+            let Some(&(dist, addr)) = self.candidates.first() else {
+                break;
+            };
+            if self
+                .responded
+                .get(CLOSEST_NODES - 1)
+                .is_some_and(|&kth| dist >= kth)
+            {
+                break;
+            }
+            self.candidates.remove(0);
+            batch.push(addr);
+        }
+        self.queried.extend(batch.iter().copied());
+        batch
+    }
+
+    /// Incorporate a node's response into the lookup
+    fn record(&mut self, resp: GetPeersResponse) {
+        let dist = xor(&resp.id, &self.target);
+        let pos = self.responded.partition_point(|&d| d < dist);
+        self.responded.insert(pos, dist);
+        for (id, node) in resp.nodes {
+            if self.queried.contains(&node)
+                || self.candidates.iter().any(|&(_, a)| a == node)
+                || node.ip().is_unspecified()
+                || node.port() == 0
+            {
+                continue;
+            }
+            let dist = xor(&id, &self.target);
+            let pos = self.candidates.partition_point(|&(d, _)| d < dist);
+            self.candidates.insert(pos, (dist, node));
+        }
+        if let Some(bf) = resp.seeds {
+            self.seeds.get_or_insert_default().merge(&bf);
+        }
+        if let Some(bf) = resp.peers {
+            self.peers.get_or_insert_default().merge(&bf);
+        }
+    }
+
+    /// Estimate the swarm size from the merged bloom filters
+    fn estimate(&self) -> Option<Scrape> {
+        if self.seeds.is_none() && self.peers.is_none() {
+            return None;
+        }
+        Some(Scrape {
+            complete: Some(self.seeds.as_ref().map_or(0, BloomFilter::estimate)),
+            incomplete: Some(self.peers.as_ref().map_or(0, BloomFilter::estimate)),
+            downloaded: None,
+            estimated: true,
+            ..Scrape::default()
+        })
+    }
+}
+
+fn xor(a: &NodeId, b: &NodeId) -> NodeId {
+    std::array::from_fn(|i| a[i] ^ b[i])
+}
+
+/// A BEP 33 bloom filter of IP addresses
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct BloomFilter([u8; BLOOM_FILTER_LEN]);
+
+impl BloomFilter {
+    fn merge(&mut self, other: &BloomFilter) {
+        for (a, b) in self.0.iter_mut().zip(other.0) {
+            *a |= b;
+        }
+    }
+
+    /// Estimate the number of distinct addresses in the filter using the
+    /// formula from BEP 33
+    // The estimate is at most about 7,800 (when only one bit is unset), so
+    // converting it to an integer cannot truncate.
+    #[expect(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn estimate(&self) -> u32 {
+        let unset = self.0.iter().map(|b| b.count_zeros()).sum::<u32>().max(1);
+        let m = f64::from(BLOOM_FILTER_BITS);
+        let size = (f64::from(unset) / m).ln() / (2.0 * (1.0 - 1.0 / m).ln());
+        size.round() as u32
+    }
+}
+
+impl Default for BloomFilter {
+    fn default() -> BloomFilter {
+        BloomFilter([0; BLOOM_FILTER_LEN])
+    }
+}
+
+impl TryFrom<&[u8]> for BloomFilter {
+    type Error = PacketError;
+
+    fn try_from(bs: &[u8]) -> Result<BloomFilter, PacketError> {
+        bs.try_into()
+            .map(BloomFilter)
+            .map_err(|_| PacketError::Short)
+    }
+}
+
+/// Encode a `get_peers` query with the BEP 33 `scrape` flag set
+fn encode_get_peers(transaction_id: &[u8], id: &NodeId, info_hash: &NodeId) -> Vec<u8> {
+    let mut encoder = Encoder::new();
+    encoder
+        .emit_dict(|mut e| {
+            e.emit_pair_with(b"a", |v| {
+                v.emit_dict(|mut a| {
+                    a.emit_pair_with(b"id", |v| v.emit_bytes(id))?;
+                    a.emit_pair_with(b"info_hash", |v| v.emit_bytes(info_hash))?;
+                    a.emit_pair(b"scrape", 1)
+                })
+            })?;
+            e.emit_pair(b"q", "get_peers")?;
+            e.emit_pair_with(b"t", |v| v.emit_bytes(transaction_id))?;
+            e.emit_pair(b"y", "q")
+        })
+        .expect("query should encode");
+    encoder.get_output().expect("query should encode")
+}
+
+/// A KRPC message received from a DHT node
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct KrpcMessage {
+    transaction_id: Vec<u8>,
+    body: KrpcBody,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum KrpcBody {
+    Response(Box<GetPeersResponse>),
+    Error(String),
+    /// A query from another node, which is ignored
+    Other,
+}
+
+impl FromBencode for KrpcMessage {
+    fn decode_bencode_object(object: Object<'_, '_>) -> Result<Self, BendyError> {
+        let mut transaction_id = None;
+        let mut kind = None;
+        let mut response = None;
+        let mut error = None;
+        let mut dd = object.try_into_dictionary()?;
+        while let Some(kv) = dd.next_pair()? {
+            match kv {
+                (b"t", val) => transaction_id = Some(val.try_into_bytes().context("t")?.to_vec()),
+                (b"y", val) => kind = Some(val.try_into_bytes().context("y")?.to_vec()),
+                (b"r", val) => {
+                    response = Some(GetPeersResponse::decode_bencode_object(val).context("r")?);
+                }
+                (b"e", val) => error = Some(decode_error(val).context("e")?),
+                _ => (),
+            }
+        }
+        let transaction_id = transaction_id.ok_or_else(|| BendyError::missing_field("t"))?;
+        let body = match kind.as_deref() {
+            Some(b"r") => KrpcBody::Response(Box::new(
+                response.ok_or_else(|| BendyError::missing_field("r"))?,
+            )),
+            Some(b"e") => KrpcBody::Error(error.ok_or_else(|| BendyError::missing_field("e"))?),
+            Some(_) => KrpcBody::Other,
+            None => return Err(BendyError::missing_field("y")),
+        };
+        Ok(KrpcMessage {
+            transaction_id,
+            body,
+        })
+    }
+}
+
+/// Decode the `[code, message]` list of a KRPC error
+fn decode_error(object: Object<'_, '_>) -> Result<String, BendyError> {
+    let mut lst = object.try_into_list()?;
+    let code = match lst.next_object()? {
+        Some(obj) => i64::decode_bencode_object(obj)?,
+        None => return Err(BendyError::missing_field("code")),
+    };
+    let msg = match lst.next_object()? {
+        Some(obj) => String::from_utf8_lossy(obj.try_into_bytes()?).into_owned(),
+        None => String::new(),
+    };
+    Ok(format!("{code} {msg}"))
+}
+
+/// The response to a `get_peers` query
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct GetPeersResponse {
+    id: NodeId,
+    /// Nodes closer to the info hash, in compact node info format
+    nodes: Vec<(NodeId, SocketAddrV4)>,
+    /// Bloom filter of seeders (BEP 33 `BFsd`)
+    seeds: Option<BloomFilter>,
+    /// Bloom filter of downloaders (BEP 33 `BFpe`)
+    peers: Option<BloomFilter>,
+}
+
+impl FromBencode for GetPeersResponse {
+    fn decode_bencode_object(object: Object<'_, '_>) -> Result<Self, BendyError> {
+        let mut id = None;
+        let mut nodes = Vec::new();
+        let mut seeds = None;
+        let mut peers = None;
+        let mut dd = object.try_into_dictionary()?;
+        while let Some(kv) = dd.next_pair()? {
+            match kv {
+                (b"id", val) => {
+                    let bs = val.try_into_bytes().context("id")?;
+                    id = Some(
+                        NodeId::try_from(bs)
+                            .map_err(|e| BendyError::malformed_content(e).context("id"))?,
+                    );
+                }
+                (b"nodes", val) => {
+                    let bs = val.try_into_bytes().context("nodes")?;
+                    nodes = TryBytes::from(bs)
+                        .try_get_all::<CompactNode>()
+                        .map_err(|e| BendyError::malformed_content(e).context("nodes"))?
+                        .into_iter()
+                        .map(|CompactNode(id, addr)| (id, addr))
+                        .collect();
+                }
+                (b"BFsd", val) => {
+                    let bs = val.try_into_bytes().context("BFsd")?;
+                    seeds = Some(
+                        BloomFilter::try_from(bs)
+                            .map_err(|e| BendyError::malformed_content(e).context("BFsd"))?,
+                    );
+                }
+                (b"BFpe", val) => {
+                    let bs = val.try_into_bytes().context("BFpe")?;
+                    peers = Some(
+                        BloomFilter::try_from(bs)
+                            .map_err(|e| BendyError::malformed_content(e).context("BFpe"))?,
+                    );
+                }
+                _ => (),
+            }
+        }
+        let id = id.ok_or_else(|| BendyError::missing_field("id"))?;
+        Ok(GetPeersResponse {
+            id,
+            nodes,
+            seeds,
+            peers,
+        })
+    }
+}
+
+/// An entry in the compact node info format of BEP 5
+struct CompactNode(NodeId, SocketAddrV4);
+
+impl TryFromBuf for CompactNode {
+    fn try_from_buf(buf: &mut Bytes) -> Result<Self, PacketError> {
+        if buf.remaining() < 20 {
+            return Err(PacketError::Short);
+        }
+        let mut id = NodeId::default();
+        buf.copy_to_slice(&mut id);
+        let addr = SocketAddrV4::try_from_buf(buf)?;
+        Ok(CompactNode(id, addr))
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum DhtError {
+    #[error("failed to bind UDP socket for DHT")]
+    Bind(#[source] std::io::Error),
+    #[error("none of the DHT bootstrap nodes could be resolved")]
+    NoBootstrap,
+    #[error("failed to send DHT query")]
+    Send(#[source] std::io::Error),
+    #[error("failed to receive DHT message")]
+    Recv(#[source] std::io::Error),
+    #[error("no DHT nodes replied to queries")]
+    NoResponse,
+    #[error("announcing to the DHT is not supported")]
+    AnnounceUnsupported,
+}
+
+impl DhtError {
+    pub(super) fn kind(&self) -> &'static str {
+        match self {
+            DhtError::Bind(_) | DhtError::NoBootstrap | DhtError::Send(_) | DhtError::Recv(_) => {
+                "network"
+            }
+            DhtError::NoResponse => "timeout",
+            DhtError::AnnounceUnsupported => "unsupported",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha1::{Digest, Sha1};
+    use std::net::IpAddr;
+
+    impl BloomFilter {
+        fn insert(&mut self, ip: IpAddr) {
+            let hash = match ip {
+                IpAddr::V4(ip) => Sha1::digest(ip.octets()),
+                IpAddr::V6(ip) => Sha1::digest(ip.octets()),
+            };
+            for index in [
+                u16::from_le_bytes([hash[0], hash[1]]),
+                u16::from_le_bytes([hash[2], hash[3]]),
+            ] {
+                let index = usize::from(index) % (BLOOM_FILTER_LEN * 8);
+                self.0[index / 8] |= 1 << (index % 8);
+            }
+        }
+    }
+
+    /// Encode a `get_peers` response as sent by a simulated node
+    fn encode_response(
+        transaction_id: &[u8],
+        id: &NodeId,
+        nodes: &[(NodeId, SocketAddrV4)],
+        seeds: Option<&BloomFilter>,
+        peers: Option<&BloomFilter>,
+    ) -> Vec<u8> {
+        let mut compact = Vec::new();
+        for (id, addr) in nodes {
+            compact.extend_from_slice(id);
+            compact.extend_from_slice(&addr.ip().octets());
+            compact.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        let mut encoder = Encoder::new();
+        encoder
+            .emit_dict(|mut e| {
+                e.emit_pair_with(b"r", |v| {
+                    v.emit_dict(|mut r| {
+                        if let Some(bf) = peers {
+                            r.emit_pair_with(b"BFpe", |v| v.emit_bytes(&bf.0))?;
+                        }
+                        if let Some(bf) = seeds {
+                            r.emit_pair_with(b"BFsd", |v| v.emit_bytes(&bf.0))?;
+                        }
+                        r.emit_pair_with(b"id", |v| v.emit_bytes(id))?;
+                        r.emit_pair_with(b"nodes", |v| v.emit_bytes(&compact))?;
+                        r.emit_pair(b"token", "abc")
+                    })
+                })?;
+                e.emit_pair_with(b"t", |v| v.emit_bytes(transaction_id))?;
+                e.emit_pair(b"y", "r")
+            })
+            .unwrap();
+        encoder.get_output().unwrap()
+    }
+
+    fn bloom_filter(ips: &[&str]) -> BloomFilter {
+        let mut bf = BloomFilter::default();
+        for ip in ips {
+            bf.insert(ip.parse().unwrap());
+        }
+        bf
+    }
+
+    /// Run a simulated DHT node that answers every `get_peers` query with the
+    /// given nodes & bloom filters
+    fn spawn_node(
+        socket: UdpSocket,
+        id: NodeId,
+        nodes: Vec<(NodeId, SocketAddrV4)>,
+        seeds: Option<BloomFilter>,
+        peers: Option<BloomFilter>,
+    ) {
+        tokio::spawn(async move {
+            let mut buf = [0; 1024];
+            loop {
+                let (len, client) = socket.recv_from(&mut buf).await.unwrap();
+                let query = &buf[..len];
+                assert!(query.windows(9).any(|w| w == b"get_peers"));
+                assert!(query.windows(11).any(|w| w == b"6:scrapei1e"));
+                let pos = query.windows(5).position(|w| w == b"1:t2:").unwrap() + 5;
+                let resp = encode_response(
+                    &query[pos..pos + 2],
+                    &id,
+                    &nodes,
+                    seeds.as_ref(),
+                    peers.as_ref(),
+                );
+                socket.send_to(&resp, client).await.unwrap();
+            }
+        });
+    }
+
+    async fn bind_node() -> (UdpSocket, SocketAddrV4) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let SocketAddr::V4(addr) = socket.local_addr().unwrap() else {
+            panic!("loopback socket should be IPv4");
+        };
+        (socket, addr)
+    }
+
+    #[test]
+    fn encode_query() {
+        let msg = encode_get_peers(b"aa", &[b'i'; 20], &[b'h'; 20]);
+        assert_eq!(
+            msg,
+            b"d1:ad2:id20:iiiiiiiiiiiiiiiiiiii9:info_hash20:hhhhhhhhhhhhhhhhhhhh6:scrapei1ee1:q9:get_peers1:t2:aa1:y1:qe"
+        );
+    }
+
+    #[test]
+    fn estimate_bloom_filter() {
+        assert_eq!(BloomFilter::default().estimate(), 0);
+        let ips = (0..100)
+            .map(|i| IpAddr::from(Ipv4Addr::new(10, 0, 1, i)))
+            .collect::<Vec<_>>();
+        let mut bf = BloomFilter::default();
+        for &ip in &ips {
+            bf.insert(ip);
+        }
+        let est = bf.estimate();
+        assert!((95..=105).contains(&est), "estimate {est} is far from 100");
+        assert_eq!(
+            BloomFilter([0xFF; BLOOM_FILTER_LEN]).estimate(),
+            BloomFilter({
+                let mut bs = [0xFF; BLOOM_FILTER_LEN];
+                bs[0] = 0xFE;
+                bs
+            })
+            .estimate()
+        );
+    }
+
+    #[test]
+    fn parse_response() {
+        let seeds = bloom_filter(&["192.0.2.1"]);
+        let node = ([7; 20], "192.0.2.2:6881".parse::<SocketAddrV4>().unwrap());
+        let buf = encode_response(b"xy", &[1; 20], &[node], Some(&seeds), None);
+        assert_eq!(
+            decode_bencode::<KrpcMessage>(&buf).unwrap(),
+            KrpcMessage {
+                transaction_id: b"xy".to_vec(),
+                body: KrpcBody::Response(Box::new(GetPeersResponse {
+                    id: [1; 20],
+                    nodes: vec![node],
+                    seeds: Some(seeds),
+                    peers: None,
+                })),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_error() {
+        let buf = b"d1:eli201e23:A Generic Error Ocurrede1:t2:aa1:y1:ee";
+        assert_eq!(
+            decode_bencode::<KrpcMessage>(buf).unwrap(),
+            KrpcMessage {
+                transaction_id: b"aa".to_vec(),
+                body: KrpcBody::Error("201 A Generic Error Ocurred".into()),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn lookup_simulated_dht() {
+        let info_hash = "28c55196f57753c40aceb6fb58617e6995a7eddb"
+            .parse::<InfoHash>()
+            .unwrap();
+        let target = NodeId::try_from(info_hash.as_bytes()).unwrap();
+        let close_id = |last: u8| {
+            let mut id = target;
+            id[19] ^= last;
+            id
+        };
+        let (bootstrap, bootstrap_addr) = bind_node().await;
+        let (node_b, addr_b) = bind_node().await;
+        let (node_c, addr_c) = bind_node().await;
+        // A node that never replies:
+        let (_node_d, addr_d) = bind_node().await;
+        spawn_node(
+            bootstrap,
+            [0xFF; 20],
+            vec![(close_id(2), addr_b), (close_id(3), addr_c)],
+            None,
+            None,
+        );
+        spawn_node(
+            node_b,
+            close_id(2),
+            vec![(close_id(1), addr_d), (close_id(3), addr_c)],
+            Some(bloom_filter(&["10.0.0.1", "10.0.0.2"])),
+            Some(bloom_filter(&["10.0.0.3"])),
+        );
+        spawn_node(
+            node_c,
+            close_id(3),
+            Vec::new(),
+            Some(bloom_filter(&["10.0.0.2", "10.0.0.4"])),
+            None,
+        );
+        let options = DhtOptions {
+            bootstrap: vec![bootstrap_addr.to_string()],
+            query_timeout: Duration::from_millis(200),
+            ..DhtOptions::default()
+        };
+        let report = DhtTracker.scrape(&[info_hash], &options).await;
+        assert_eq!(
+            report.get(info_hash).unwrap(),
+            Some(Scrape {
+                complete: Some(3),
+                incomplete: Some(1),
+                downloaded: None,
+                estimated: true,
+                ..Scrape::default()
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn lookup_without_responses() {
+        let info_hash = "28c55196f57753c40aceb6fb58617e6995a7eddb"
+            .parse::<InfoHash>()
+            .unwrap();
+        let (_silent, addr) = bind_node().await;
+        let options = DhtOptions {
+            bootstrap: vec![addr.to_string()],
+            query_timeout: Duration::from_millis(50),
+            ..DhtOptions::default()
+        };
+        let report = DhtTracker.scrape(&[info_hash], &options).await;
+        let e = report.get(info_hash).unwrap_err();
+        assert!(matches!(*e, TrackerError::Dht(DhtError::NoResponse)));
+    }
+}
@@ -1,10 +1,21 @@
-use super::{Scrape, ScrapeMap, TrackerError, TrackerUrlError};
+use super::{
+    AnnounceParams, AnnounceResult, Announcement, IpFamily, Scrape, ScrapeMap, ScrapeReport,
+    TrackerError, TrackerUrlError, with_timeout,
+};
 use crate::infohash::InfoHash;
-use crate::util::{UnbencodeError, decode_bencode};
+use crate::util::{TryBytes, UnbencodeError, add_bytes_query_param, decode_bencode_with};
 use bendy::decoding::{Error as BendyError, FromBencode, Object, ResultExt};
-use reqwest::Client;
+use reqwest::cookie::Jar;
+use reqwest::header::HeaderMap;
+use reqwest::{Certificate, Client, Proxy};
 use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
+use tokio::time::{Instant, sleep_until};
 use url::Url;
 
 static USER_AGENT: &str = concat!(
@@ -19,20 +30,241 @@ static USER_AGENT: &str = concat!(
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub(crate) struct HttpTracker(Url);
 
+/// Settings for communicating with HTTP trackers
+#[derive(Clone, Debug)]
+pub(crate) struct HttpOptions {
+    /// Maximum number of info hashes to send in a single scrape request
+    pub(crate) batch_size: NonZeroUsize,
+    /// Maximum length of a scrape request URL.  A request for a single info
+    /// hash is always made, even if its URL is longer than this.
+    pub(crate) max_url_len: usize,
+    /// Maximum amount of time to wait for each request to complete
+    pub(crate) timeout: Duration,
+    /// If set, only connect to trackers over this IP address family
+    pub(crate) family: Option<IpFamily>,
+    /// Proxy through which to send all requests
+    pub(crate) proxy: Option<Proxy>,
+    /// Additional headers to send with each request
+    pub(crate) headers: HeaderMap,
+    /// Cookies to send to trackers
+    pub(crate) cookies: Option<Arc<Jar>>,
+    /// `User-Agent` header to send in place of the default
+    pub(crate) user_agent: Option<String>,
+    /// Certificates to trust as TLS roots in addition to the system's
+    pub(crate) root_certs: Vec<Certificate>,
+    /// Whether to accept invalid TLS certificates.  This is dangerous.
+    pub(crate) insecure: bool,
+    /// Whether to accept non-conforming responses where possible
+    pub(crate) lenient: bool,
+}
+
+impl Default for HttpOptions {
+    fn default() -> HttpOptions {
+        HttpOptions {
+            batch_size: NonZeroUsize::new(50).expect("50 should be nonzero"),
+            max_url_len: 4096,
+            timeout: Duration::from_secs(30),
+            family: None,
+            proxy: None,
+            headers: HeaderMap::new(),
+            cookies: None,
+            user_agent: None,
+            root_certs: Vec::new(),
+            insecure: false,
+            lenient: false,
+        }
+    }
+}
+
 impl HttpTracker {
     #[tracing::instrument(name = "scrape-http", skip_all, fields(tracker = %self.0))]
-    pub(crate) async fn scrape(&self, hashes: &[InfoHash]) -> Result<ScrapeMap, TrackerError> {
-        let client = Client::builder()
-            .user_agent(USER_AGENT)
-            .build()
-            .map_err(HttpTrackerError::BuildClient)?;
+    pub(crate) async fn scrape(&self, hashes: &[InfoHash], options: &HttpOptions) -> ScrapeReport {
+        let mut report = ScrapeReport::default();
+        if !self.supports_scrape() {
+            report.fail(hashes, TrackerError::ScrapeUnsupported);
+            return report;
+        }
+        let client = match build_client(options) {
+            Ok(client) => client,
+            Err(e) => {
+                report.fail(hashes, e.into());
+                return report;
+            }
+        };
+        let mut batches = self
+            .scrape_urls(hashes, options.batch_size.get(), options.max_url_len)
+            .into_iter();
+        let mut done = 0;
+        let mut rebatched = false;
+        let mut next_request: Option<(Instant, u32)> = None;
+        while let Some((url, batch)) = batches.next() {
+            if let Some((when, secs)) = next_request {
+                // Don't wait longer for the tracker than we would for a
+                // reply to a single request.
+                if when.saturating_duration_since(Instant::now()) > options.timeout {
+                    report.fail(
+                        &hashes[done..],
+                        HttpTrackerError::MinRequestInterval(secs).into(),
+                    );
+                    break;
+                }
+                tracing::info!("Waiting for tracker's minimum request interval before next scrape");
+                sleep_until(when).await;
+            }
+            let start = Instant::now();
+            let r = with_timeout(
+                options.timeout,
+                self.scrape_url(&client, url, options.lenient),
+            )
+            .await
+            .map(|(scrapes, min_interval, warning)| {
+                next_request =
+                    min_interval.map(|secs| (start + Duration::from_secs(secs.into()), secs));
+                if let Some(msg) = warning {
+                    report.warn(msg);
+                }
+                scrapes
+            });
+            report.record(batch, r);
+            done += batch.len();
+            if next_request.is_some() && !rebatched {
+                // Every request now costs a wait, so make as few more as the
+                // URL length limit allows.
+                batches = self
+                    .scrape_urls(&hashes[done..], usize::MAX, options.max_url_len)
+                    .into_iter();
+                rebatched = true;
+            }
+        }
+        report
+    }
+
+    /// Announce each of the given info hashes to the tracker in turn and
+    /// return the results in the same order
+    #[tracing::instrument(name = "announce-http", skip_all, fields(tracker = %self.0))]
+    pub(crate) async fn announce(
+        &self,
+        hashes: &[InfoHash],
+        params: &AnnounceParams,
+        options: &HttpOptions,
+    ) -> Vec<AnnounceResult> {
+        let client = match build_client(options) {
+            Ok(client) => client,
+            Err(e) => {
+                let e = Arc::new(TrackerError::from(e));
+                return hashes.iter().map(|_| Err(Arc::clone(&e))).collect();
+            }
+        };
+        let mut results = Vec::with_capacity(hashes.len());
+        for &ih in hashes {
+            let url = self.announce_url(ih, params);
+            let r = with_timeout(
+                options.timeout,
+                self.announce_url_request(&client, url, options.lenient),
+            )
+            .await;
+            results.push(r.map_err(Arc::new));
+        }
+        results
+    }
+
+    /// Construct the announce request URL for the given info hash
+    fn announce_url(&self, info_hash: InfoHash, params: &AnnounceParams) -> Url {
         let mut url = self.0.clone();
-        url.set_path(&url.path().replace("announce", "scrape"));
         url.set_fragment(None);
-        for ih in hashes {
-            ih.add_query_param(&mut url);
+        info_hash.add_query_param(&mut url);
+        add_bytes_query_param(&mut url, "peer_id", &params.peer_id);
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("port", &params.port.to_string())
+                .append_pair("uploaded", &params.uploaded.to_string())
+                .append_pair("downloaded", &params.downloaded.to_string())
+                .append_pair("left", &params.left.to_string())
+                .append_pair("key", &format!("{:08x}", params.key))
+                .append_pair("compact", "1");
+            if let Some(event) = params.event.http_value() {
+                query.append_pair("event", event);
+            }
+            if let Some(numwant) = params.numwant {
+                query.append_pair("numwant", &numwant.to_string());
+            }
         }
-        let buf = client
+        url
+    }
+
+    async fn announce_url_request(
+        &self,
+        client: &Client,
+        url: Url,
+        lenient: bool,
+    ) -> Result<Announcement, TrackerError> {
+        tracing::info!(%url, "Sending announce request to tracker");
+        let buf = self.get(client, url).await?;
+        HttpAnnounceResponse::parse(&buf, lenient)
+            .map_err(HttpTrackerError::ParseResponse)?
+            .result()
+    }
+
+    /// Per BEP 48, a tracker's scrape URL is derived from its announce URL by
+    /// replacing "announce" in the path with "scrape"; trackers whose URLs do
+    /// not contain "announce" thus do not support scraping.
+    fn supports_scrape(&self) -> bool {
+        self.0.path().contains("announce")
+    }
+
+    /// Construct the scrape request URLs for the given info hashes, splitting
+    /// them into batches of at most `batch_size` info hashes and URLs of at
+    /// most `max_url_len` bytes.  Each URL is returned along with the info
+    /// hashes it requests.
+    fn scrape_urls<'a>(
+        &self,
+        hashes: &'a [InfoHash],
+        batch_size: usize,
+        max_url_len: usize,
+    ) -> Vec<(Url, &'a [InfoHash])> {
+        let mut base = self.0.clone();
+        base.set_path(&base.path().replace("announce", "scrape"));
+        base.set_fragment(None);
+        let mut urls = Vec::new();
+        let mut url = base.clone();
+        let mut start = 0;
+        for (i, ih) in hashes.iter().enumerate() {
+            let mut next = url.clone();
+            ih.add_query_param(&mut next);
+            let qty = i - start;
+            if qty > 0 && (qty >= batch_size || next.as_str().len() > max_url_len) {
+                urls.push((std::mem::replace(&mut url, base.clone()), &hashes[start..i]));
+                next = base.clone();
+                ih.add_query_param(&mut next);
+                start = i;
+            }
+            url = next;
+        }
+        if start < hashes.len() {
+            urls.push((url, &hashes[start..]));
+        }
+        urls
+    }
+
+    /// Perform a single scrape request and return the scrapes along with the
+    /// tracker's minimum request interval and warning message, if any
+    async fn scrape_url(
+        &self,
+        client: &Client,
+        url: Url,
+        lenient: bool,
+    ) -> Result<(ScrapeMap, Option<u32>, Option<String>), TrackerError> {
+        tracing::info!(%url, "Sending scrape request to tracker");
+        let buf = self.get(client, url).await?;
+        HttpScrapeResponse::parse(&buf, lenient)
+            .map_err(HttpTrackerError::ParseResponse)?
+            .result()
+    }
+
+    /// Perform a GET request and return the response body
+    async fn get(&self, client: &Client, url: Url) -> Result<bytes::Bytes, HttpTrackerError> {
+        client
             .get(url)
             .send()
             .await
@@ -41,10 +273,34 @@ impl HttpTracker {
             .map_err(HttpTrackerError::HttpStatus)?
             .bytes()
             .await
-            .map_err(HttpTrackerError::ReadBody)?;
-        decode_bencode::<HttpScrapeResponse>(&buf)
-            .map_err(HttpTrackerError::ParseResponse)?
-            .result()
+            .map_err(HttpTrackerError::ReadBody)
+    }
+}
+
+/// Construct an HTTP client for communicating with trackers
+fn build_client(options: &HttpOptions) -> Result<Client, HttpTrackerError> {
+    let mut builder = Client::builder()
+        .user_agent(options.user_agent.as_deref().unwrap_or(USER_AGENT))
+        .default_headers(options.headers.clone())
+        .tls_certs_merge(options.root_certs.iter().cloned())
+        .tls_danger_accept_invalid_certs(options.insecure);
+    if let Some(family) = options.family {
+        // Binding to a local address of one family makes the client only
+        // connect to remote addresses of that family.
+        builder = builder.local_address(family.unspecified());
+    }
+    if let Some(proxy) = &options.proxy {
+        builder = builder.proxy(proxy.clone());
+    }
+    if let Some(jar) = &options.cookies {
+        builder = builder.cookie_provider(Arc::clone(jar));
+    }
+    builder.build().map_err(HttpTrackerError::BuildClient)
+}
+
+impl fmt::Display for HttpTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
@@ -59,32 +315,56 @@ impl TryFrom<Url> for HttpTracker {
         if url.host().is_none() {
             return Err(TrackerUrlError::NoHost);
         }
-        if !url.path().contains("announce") {
-            return Err(TrackerUrlError::NoAnnounce);
-        }
         Ok(HttpTracker(url))
     }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 enum HttpScrapeResponse {
-    Success(ScrapeMap),
+    Success {
+        files: ScrapeMap,
+        /// The BEP 48 `flags.min_request_interval` value, also recorded on
+        /// each of the scrapes in `files`
+        min_request_interval: Option<u32>,
+        /// The tracker's warning message, also recorded on each of the
+        /// scrapes in `files`
+        warning: Option<String>,
+    },
     Failure(String),
 }
 
 impl HttpScrapeResponse {
-    fn result(self) -> Result<ScrapeMap, TrackerError> {
+    /// Return the scrapes along with the minimum number of seconds to wait
+    /// before the next scrape request and the tracker's warning message, if
+    /// the tracker sent them
+    fn result(self) -> Result<(ScrapeMap, Option<u32>, Option<String>), TrackerError> {
         match self {
-            HttpScrapeResponse::Success(scrape) => Ok(scrape),
+            HttpScrapeResponse::Success {
+                files,
+                min_request_interval,
+                warning,
+            } => Ok((files, min_request_interval, warning)),
             HttpScrapeResponse::Failure(msg) => Err(TrackerError::Failure(msg)),
         }
     }
 }
 
-impl FromBencode for HttpScrapeResponse {
-    fn decode_bencode_object(object: Object<'_, '_>) -> Result<Self, BendyError> {
+impl HttpScrapeResponse {
+    /// Parse a scrape response body.  If `lenient` is true, non-conforming
+    /// responses are accepted where possible.
+    fn parse(buf: &[u8], lenient: bool) -> Result<Self, UnbencodeError> {
+        decode_bencode_with(buf, Self::EXPECTED_RECURSION_DEPTH, lenient, |obj| {
+            Self::decode(obj, lenient)
+        })
+    }
+
+    /// Decode a scrape response.  If `lenient` is true, missing statistics
+    /// are treated as unknown, and out-of-range numbers are clamped.
+    fn decode(object: Object<'_, '_>, lenient: bool) -> Result<Self, BendyError> {
         let mut files = None;
         let mut failure_reason = None;
+        let mut min_request_interval = None;
+        let mut warning = None;
         let mut dd = object.try_into_dictionary()?;
         while let Some(kv) = dd.next_pair()? {
             match kv {
@@ -97,64 +377,269 @@ impl FromBencode for HttpScrapeResponse {
                         let mut complete = None;
                         let mut downloaded = None;
                         let mut incomplete = None;
+                        let mut name = None;
+                        let mut downloaders = None;
                         let mut vdict = v.try_into_dictionary().context("files.<value>")?;
                         while let Some(kv) = vdict.next_pair().context("files.<value>")? {
                             match kv {
                                 (b"complete", val) => {
                                     complete = Some(
-                                        u32::decode_bencode_object(val)
-                                            .context("files.*.complete")?,
+                                        decode_count(val, lenient).context("files.*.complete")?,
                                     );
                                 }
                                 (b"downloaded", val) => {
                                     downloaded = Some(
-                                        u32::decode_bencode_object(val)
-                                            .context("files.*.downloaded")?,
+                                        decode_count(val, lenient).context("files.*.downloaded")?,
                                     );
                                 }
                                 (b"incomplete", val) => {
                                     incomplete = Some(
-                                        u32::decode_bencode_object(val)
-                                            .context("files.*.incomplete")?,
+                                        decode_count(val, lenient).context("files.*.incomplete")?,
+                                    );
+                                }
+                                (b"name", val) => {
+                                    name = Some(decode_lossy(val).context("files.*.name")?);
+                                }
+                                (b"downloaders", val) => {
+                                    downloaders = Some(
+                                        decode_count(val, lenient)
+                                            .context("files.*.downloaders")?,
                                     );
                                 }
                                 _ => (),
                             }
                         }
-                        let complete = complete
-                            .ok_or_else(|| BendyError::missing_field("files.*.complete"))?;
-                        let downloaded = downloaded
-                            .ok_or_else(|| BendyError::missing_field("files.*.downloaded"))?;
-                        let incomplete = incomplete
-                            .ok_or_else(|| BendyError::missing_field("files.*.incomplete"))?;
+                        if !lenient {
+                            if complete.is_none() {
+                                return Err(BendyError::missing_field("files.*.complete"));
+                            }
+                            if downloaded.is_none() {
+                                return Err(BendyError::missing_field("files.*.downloaded"));
+                            }
+                            if incomplete.is_none() {
+                                return Err(BendyError::missing_field("files.*.incomplete"));
+                            }
+                        }
                         filemap.insert(
                             infohash,
                             Scrape {
                                 complete,
                                 incomplete,
                                 downloaded,
+                                name,
+                                downloaders,
+                                ..Scrape::default()
                             },
                         );
                     }
                     files = Some(filemap);
                 }
                 (b"failure reason", val) => {
-                    failure_reason = Some(
-                        String::from_utf8_lossy(val.try_into_bytes().context("failure reason")?)
-                            .into_owned(),
-                    );
+                    failure_reason = Some(decode_lossy(val).context("failure reason")?);
+                }
+                (b"flags", val) => {
+                    let mut fdict = val.try_into_dictionary().context("flags")?;
+                    while let Some(kv) = fdict.next_pair().context("flags")? {
+                        if let (b"min_request_interval", val) = kv {
+                            min_request_interval = Some(
+                                decode_count(val, lenient).context("flags.min_request_interval")?,
+                            );
+                        }
+                    }
+                }
+                (b"warning message", val) => {
+                    warning = Some(decode_lossy(val).context("warning message")?);
                 }
                 _ => (),
             }
         }
         match (files, failure_reason) {
-            (Some(files), None) => Ok(HttpScrapeResponse::Success(files)),
+            (Some(mut files), None) => {
+                for s in files.values_mut() {
+                    s.min_request_interval = min_request_interval;
+                    s.warning.clone_from(&warning);
+                }
+                Ok(HttpScrapeResponse::Success {
+                    files,
+                    min_request_interval,
+                    warning,
+                })
+            }
             (_, Some(fr)) => Ok(HttpScrapeResponse::Failure(fr)),
             (None, None) => Err(BendyError::missing_field("files")),
         }
     }
 }
 
+impl FromBencode for HttpScrapeResponse {
+    fn decode_bencode_object(object: Object<'_, '_>) -> Result<Self, BendyError> {
+        HttpScrapeResponse::decode(object, false)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum HttpAnnounceResponse {
+    Success(Announcement),
+    Failure(String),
+}
+
+impl HttpAnnounceResponse {
+    fn result(self) -> Result<Announcement, TrackerError> {
+        match self {
+            HttpAnnounceResponse::Success(ann) => Ok(ann),
+            HttpAnnounceResponse::Failure(msg) => Err(TrackerError::Failure(msg)),
+        }
+    }
+}
+
+impl HttpAnnounceResponse {
+    /// Parse an announce response body.  If `lenient` is true, non-conforming
+    /// responses are accepted where possible.
+    fn parse(buf: &[u8], lenient: bool) -> Result<Self, UnbencodeError> {
+        decode_bencode_with(buf, Self::EXPECTED_RECURSION_DEPTH, lenient, |obj| {
+            Self::decode(obj, lenient)
+        })
+    }
+
+    /// Decode an announce response.  If `lenient` is true, out-of-range
+    /// numbers are clamped.
+    fn decode(object: Object<'_, '_>, lenient: bool) -> Result<Self, BendyError> {
+        let mut failure_reason = None;
+        let mut interval = None;
+        let mut min_interval = None;
+        let mut seeders = None;
+        let mut leechers = None;
+        let mut tracker_id = None;
+        let mut warning = None;
+        let mut peers = Vec::new();
+        let mut dd = object.try_into_dictionary()?;
+        while let Some(kv) = dd.next_pair()? {
+            match kv {
+                (b"failure reason", val) => {
+                    failure_reason = Some(decode_lossy(val).context("failure reason")?);
+                }
+                (b"warning message", val) => {
+                    warning = Some(decode_lossy(val).context("warning message")?);
+                }
+                (b"tracker id", val) => {
+                    tracker_id = Some(decode_lossy(val).context("tracker id")?);
+                }
+                (b"interval", val) => {
+                    interval = Some(decode_count(val, lenient).context("interval")?);
+                }
+                (b"min interval", val) => {
+                    min_interval = Some(decode_count(val, lenient).context("min interval")?);
+                }
+                (b"complete", val) => {
+                    seeders = Some(decode_count(val, lenient).context("complete")?);
+                }
+                (b"incomplete", val) => {
+                    leechers = Some(decode_count(val, lenient).context("incomplete")?);
+                }
+                (b"peers", Object::Bytes(bs)) => {
+                    // Compact model (BEP 23)
+                    peers.extend(
+                        TryBytes::from(bs)
+                            .try_get_all::<SocketAddrV4>()
+                            .map_err(|e| BendyError::malformed_content(e).context("peers"))?
+                            .into_iter()
+                            .map(SocketAddr::V4),
+                    );
+                }
+                (b"peers", Object::List(mut lst)) => {
+                    // Dictionary model
+                    while let Some(p) = lst.next_object().context("peers")? {
+                        if let Some(addr) = decode_peer_dict(p).context("peers.*")? {
+                            peers.push(addr);
+                        }
+                    }
+                }
+                (b"peers", obj) => {
+                    return Err(BendyError::unexpected_token(
+                        "String or List",
+                        obj.into_token().name(),
+                    )
+                    .context("peers"));
+                }
+                (b"peers6", val) => {
+                    // Compact IPv6 peers (BEP 7)
+                    let bs = val.try_into_bytes().context("peers6")?;
+                    peers.extend(
+                        TryBytes::from(bs)
+                            .try_get_all::<SocketAddrV6>()
+                            .map_err(|e| BendyError::malformed_content(e).context("peers6"))?
+                            .into_iter()
+                            .map(SocketAddr::V6),
+                    );
+                }
+                _ => (),
+            }
+        }
+        if let Some(fr) = failure_reason {
+            return Ok(HttpAnnounceResponse::Failure(fr));
+        }
+        let interval = interval.ok_or_else(|| BendyError::missing_field("interval"))?;
+        Ok(HttpAnnounceResponse::Success(Announcement {
+            interval,
+            min_interval,
+            seeders,
+            leechers,
+            tracker_id,
+            warning,
+            peers,
+        }))
+    }
+}
+
+impl FromBencode for HttpAnnounceResponse {
+    fn decode_bencode_object(object: Object<'_, '_>) -> Result<Self, BendyError> {
+        HttpAnnounceResponse::decode(object, false)
+    }
+}
+
+/// Decode a count or interval.  Values that do not fit in a `u32` are an
+/// error unless `lenient` is true, in which case they are clamped to the
+/// range of `u32`.
+fn decode_count(object: Object<'_, '_>, lenient: bool) -> Result<u32, BendyError> {
+    if !lenient {
+        return u32::decode_bencode_object(object);
+    }
+    let digits = object.try_into_integer()?;
+    let value = match digits.parse::<i128>() {
+        Ok(n) => u32::try_from(n.max(0)).unwrap_or(u32::MAX),
+        // The integer is too large to parse, and it has already been
+        // validated as an integer by the decoder.
+        Err(_) if digits.starts_with('-') => 0,
+        Err(_) => u32::MAX,
+    };
+    Ok(value)
+}
+
+/// Decode a peer in the dictionary model of announce responses.  Returns
+/// `None` if the peer's "ip" is a hostname rather than an IP address.
+fn decode_peer_dict(object: Object<'_, '_>) -> Result<Option<SocketAddr>, BendyError> {
+    let mut ip = None;
+    let mut port = None;
+    let mut dd = object.try_into_dictionary()?;
+    while let Some(kv) = dd.next_pair()? {
+        match kv {
+            (b"ip", val) => ip = Some(decode_lossy(val).context("ip")?),
+            (b"port", val) => port = Some(u16::decode_bencode_object(val).context("port")?),
+            _ => (),
+        }
+    }
+    let ip = ip.ok_or_else(|| BendyError::missing_field("ip"))?;
+    let port = port.ok_or_else(|| BendyError::missing_field("port"))?;
+    Ok(ip
+        .parse::<IpAddr>()
+        .ok()
+        .map(|ip| SocketAddr::new(ip, port)))
+}
+
+fn decode_lossy(object: Object<'_, '_>) -> Result<String, BendyError> {
+    Ok(String::from_utf8_lossy(object.try_into_bytes()?).into_owned())
+}
+
 #[derive(Debug, Error)]
 pub(crate) enum HttpTrackerError {
     #[error("failed to build HTTP client")]
@@ -167,11 +652,28 @@ pub(crate) enum HttpTrackerError {
     ReadBody(#[source] reqwest::Error),
     #[error("failed to parse HTTP tracker response")]
     ParseResponse(#[source] UnbencodeError),
+    #[error("tracker's minimum request interval of {0} seconds exceeds the timeout")]
+    MinRequestInterval(u32),
+}
+
+impl HttpTrackerError {
+    pub(super) fn kind(&self) -> &'static str {
+        match self {
+            HttpTrackerError::BuildClient(_)
+            | HttpTrackerError::SendRequest(_)
+            | HttpTrackerError::ReadBody(_) => "network",
+            HttpTrackerError::HttpStatus(_) => "http-status",
+            HttpTrackerError::ParseResponse(_) => "protocol",
+            HttpTrackerError::MinRequestInterval(_) => "rate-limited",
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::tracker::AnnounceEvent;
+    use crate::util::decode_bencode;
     use bytes::{BufMut, BytesMut};
 
     #[test]
@@ -189,10 +691,12 @@ mod tests {
         );
         buf.put(b"d8:completei105e10:downloadedi1337e10:incompletei42ee".as_slice());
         buf.put(b"ee".as_slice());
-        let res = decode_bencode::<HttpScrapeResponse>(&buf)
+        let (res, min_interval, warning) = decode_bencode::<HttpScrapeResponse>(&buf)
             .unwrap()
             .result()
             .unwrap();
+        assert_eq!(min_interval, None);
+        assert_eq!(warning, None);
         assert_eq!(
             res,
             HashMap::from([
@@ -201,9 +705,10 @@ mod tests {
                         .parse::<InfoHash>()
                         .unwrap(),
                     Scrape {
-                        complete: 10,
-                        incomplete: 0,
-                        downloaded: 32
+                        complete: Some(10),
+                        incomplete: Some(0),
+                        downloaded: Some(32),
+                        ..Scrape::default()
                     },
                 ),
                 (
@@ -211,15 +716,265 @@ mod tests {
                         .parse::<InfoHash>()
                         .unwrap(),
                     Scrape {
-                        complete: 105,
-                        incomplete: 42,
-                        downloaded: 1337
+                        complete: Some(105),
+                        incomplete: Some(42),
+                        downloaded: Some(1337),
+                        ..Scrape::default()
                     },
                 ),
             ])
         );
     }
 
+    #[test]
+    fn parse_scrape_response_extras() {
+        let mut buf = BytesMut::new();
+        buf.put(b"d5:filesd".as_slice());
+        buf.put(
+            b"20:\x28\xc5\x51\x96\xf5\x77\x53\xc4\x0a\xce\xb6\xfb\x58\x61\x7e\x69\x95\xa7\xed\xdb"
+                .as_slice(),
+        );
+        buf.put(b"d8:completei10e10:downloadedi32e11:downloadersi3e".as_slice());
+        buf.put(b"10:incompletei5e4:name8:Big Bucke".as_slice());
+        buf.put(b"e5:flagsd20:min_request_intervali1800ee".as_slice());
+        buf.put(b"15:warning message14:Slow down, pale".as_slice());
+        let (res, min_interval, warning) = decode_bencode::<HttpScrapeResponse>(&buf)
+            .unwrap()
+            .result()
+            .unwrap();
+        assert_eq!(min_interval, Some(1800));
+        assert_eq!(warning.as_deref(), Some("Slow down, pal"));
+        assert_eq!(
+            res,
+            HashMap::from([(
+                "28c55196f57753c40aceb6fb58617e6995a7eddb"
+                    .parse::<InfoHash>()
+                    .unwrap(),
+                Scrape {
+                    complete: Some(10),
+                    incomplete: Some(5),
+                    downloaded: Some(32),
+                    min_request_interval: Some(1800),
+                    name: Some(String::from("Big Buck")),
+                    downloaders: Some(3),
+                    warning: Some(String::from("Slow down, pal")),
+                    ..Scrape::default()
+                },
+            )])
+        );
+    }
+
+    #[test]
+    fn parse_scrape_response_warning_without_files() {
+        let resp = decode_bencode::<HttpScrapeResponse>(b"d5:filesde15:warning message7:Carefule")
+            .unwrap();
+        assert_eq!(
+            resp,
+            HttpScrapeResponse::Success {
+                files: ScrapeMap::new(),
+                min_request_interval: None,
+                warning: Some(String::from("Careful")),
+            }
+        );
+    }
+
+    /// Serve `count` scrape requests on a local port, replying to each with no
+    /// files and the given `min_request_interval`.  Returns the tracker and a
+    /// handle that yields the time at which each request was received.
+    async fn min_interval_tracker(
+        count: usize,
+        min_interval: u32,
+    ) -> (HttpTracker, tokio::task::JoinHandle<Vec<Instant>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let mut times = Vec::new();
+            for _ in 0..count {
+                let (mut conn, _) = listener.accept().await.unwrap();
+                times.push(Instant::now());
+                let mut buf = [0; 4096];
+                let mut len = 0;
+                while !buf[..len].ends_with(b"\r\n\r\n") {
+                    len += conn.read(&mut buf[len..]).await.unwrap();
+                }
+                let body = format!("d5:filesde5:flagsd20:min_request_intervali{min_interval}eee");
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                conn.write_all(head.as_bytes()).await.unwrap();
+                conn.write_all(body.as_bytes()).await.unwrap();
+            }
+            times
+        });
+        let tracker =
+            HttpTracker::try_from(format!("http://{addr}/announce").parse::<Url>().unwrap())
+                .unwrap();
+        (tracker, server)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wait_for_min_request_interval() {
+        // After the first response, the remaining info hashes are all sent in
+        // a single request.
+        let (tracker, server) = min_interval_tracker(2, 5).await;
+        let hashes = [
+            "28c55196f57753c40aceb6fb58617e6995a7eddb",
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            "b851474b74f65cd19f981c723590e3e520242b97",
+        ]
+        .map(|s| s.parse::<InfoHash>().unwrap());
+        let options = HttpOptions {
+            batch_size: NonZeroUsize::new(1).unwrap(),
+            ..HttpOptions::default()
+        };
+        let report = tracker.scrape(&hashes, &options).await;
+        for ih in hashes {
+            assert_eq!(report.get(ih).unwrap(), None);
+        }
+        let times = server.await.unwrap();
+        assert!(times[1] - times[0] >= Duration::from_secs(5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn min_request_interval_exceeds_timeout() {
+        let (tracker, server) = min_interval_tracker(1, 1800).await;
+        let hashes = [
+            "28c55196f57753c40aceb6fb58617e6995a7eddb",
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            "b851474b74f65cd19f981c723590e3e520242b97",
+        ]
+        .map(|s| s.parse::<InfoHash>().unwrap());
+        let options = HttpOptions {
+            batch_size: NonZeroUsize::new(1).unwrap(),
+            ..HttpOptions::default()
+        };
+        let report = tracker.scrape(&hashes, &options).await;
+        assert_eq!(report.get(hashes[0]).unwrap(), None);
+        for ih in &hashes[1..] {
+            let e = report.get(*ih).unwrap_err();
+            assert!(matches!(
+                *e,
+                TrackerError::Http(HttpTrackerError::MinRequestInterval(1800))
+            ));
+        }
+        assert_eq!(server.await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn batch_scrape_urls() {
+        let tracker = HttpTracker(
+            "http://tracker.example.com/announce?key=abc"
+                .parse::<Url>()
+                .unwrap(),
+        );
+        let hashes = [
+            "28c55196f57753c40aceb6fb58617e6995a7eddb",
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            "b851474b74f65cd19f981c723590e3e520242b97",
+        ]
+        .map(|s| s.parse::<InfoHash>().unwrap());
+        let urls = tracker.scrape_urls(&hashes, 2, 4096);
+        assert_eq!(
+            urls.iter()
+                .map(|(_, batch)| batch.len())
+                .collect::<Vec<_>>(),
+            [2, 1]
+        );
+        assert_eq!(
+            urls.iter().map(|(u, _)| u.as_str()).collect::<Vec<_>>(),
+            [
+                "http://tracker.example.com/scrape?key=abc&info_hash=%28%C5Q%96%F5wS%C4%0A%CE%B6%FBXa%7Ei%95%A7%ED%DB&info_hash=%DA9%A3%EE%5EkK%0D2U%BF%EF%95%60%18%90%AF%D8%07%09",
+                "http://tracker.example.com/scrape?key=abc&info_hash=%B8QGKt%F6%5C%D1%9F%98%1Cr5%90%E3%E5+%24%2B%97",
+            ]
+        );
+    }
+
+    #[test]
+    fn batch_scrape_urls_by_length() {
+        let tracker = HttpTracker(
+            "http://tracker.example.com/announce"
+                .parse::<Url>()
+                .unwrap(),
+        );
+        let hashes = [
+            "28c55196f57753c40aceb6fb58617e6995a7eddb",
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            "b851474b74f65cd19f981c723590e3e520242b97",
+        ]
+        .map(|s| s.parse::<InfoHash>().unwrap());
+        let urls = tracker.scrape_urls(&hashes, 50, 40);
+        assert_eq!(urls.len(), 3);
+        assert!(
+            urls.iter()
+                .all(|(u, batch)| u.query_pairs().count() == 1 && batch.len() == 1)
+        );
+    }
+
+    #[test]
+    fn parse_nonconforming_scrape_response() {
+        let mut buf = BytesMut::new();
+        buf.put(b"d5:filesd".as_slice());
+        buf.put(
+            b"20:\x28\xc5\x51\x96\xf5\x77\x53\xc4\x0a\xce\xb6\xfb\x58\x61\x7e\x69\x95\xa7\xed\xdb"
+                .as_slice(),
+        );
+        buf.put(b"d8:completei-3e10:incompletei99999999999999999999999ee".as_slice());
+        buf.put(b"ee\n".as_slice());
+        assert!(HttpScrapeResponse::parse(&buf, false).is_err());
+        let (res, _, _) = HttpScrapeResponse::parse(&buf, true)
+            .unwrap()
+            .result()
+            .unwrap();
+        assert_eq!(
+            res,
+            HashMap::from([(
+                "28c55196f57753c40aceb6fb58617e6995a7eddb"
+                    .parse::<InfoHash>()
+                    .unwrap(),
+                Scrape {
+                    complete: Some(0),
+                    incomplete: Some(u32::MAX),
+                    downloaded: None,
+                    ..Scrape::default()
+                },
+            )])
+        );
+    }
+
+    #[test]
+    fn parse_scrape_response_missing_fields() {
+        let mut buf = BytesMut::new();
+        buf.put(b"d5:filesd".as_slice());
+        buf.put(
+            b"20:\x28\xc5\x51\x96\xf5\x77\x53\xc4\x0a\xce\xb6\xfb\x58\x61\x7e\x69\x95\xa7\xed\xdb"
+                .as_slice(),
+        );
+        buf.put(b"d10:downloadedi5eeee".as_slice());
+        assert!(HttpScrapeResponse::parse(&buf, false).is_err());
+        let (res, _, _) = HttpScrapeResponse::parse(&buf, true)
+            .unwrap()
+            .result()
+            .unwrap();
+        assert_eq!(
+            res,
+            HashMap::from([(
+                "28c55196f57753c40aceb6fb58617e6995a7eddb"
+                    .parse::<InfoHash>()
+                    .unwrap(),
+                Scrape {
+                    complete: None,
+                    incomplete: None,
+                    downloaded: Some(5),
+                    ..Scrape::default()
+                },
+            )])
+        );
+    }
+
     #[test]
     fn parse_scrape_failure_response() {
         let mut buf = BytesMut::new();
@@ -230,4 +985,131 @@ mod tests {
             HttpScrapeResponse::Failure(String::from("Out of bits"))
         );
     }
+
+    #[test]
+    fn build_announce_url() {
+        let tracker = HttpTracker(
+            "http://tracker.example.com/announce?passkey=abc"
+                .parse::<Url>()
+                .unwrap(),
+        );
+        let params = AnnounceParams {
+            peer_id: *b"-TS0000-\x00\x01\x02 abcdefgh",
+            key: 0xDEADBEEF,
+            port: 6881,
+            uploaded: 0,
+            downloaded: 1024,
+            left: 2048,
+            event: AnnounceEvent::Started,
+            numwant: Some(50),
+        };
+        let info_hash = "28c55196f57753c40aceb6fb58617e6995a7eddb"
+            .parse::<InfoHash>()
+            .unwrap();
+        let url = tracker.announce_url(info_hash, &params);
+        assert_eq!(
+            url.as_str(),
+            "http://tracker.example.com/announce?passkey=abc&info_hash=%28%C5Q%96%F5wS%C4%0A%CE%B6%FBXa%7Ei%95%A7%ED%DB&peer_id=-TS0000-%00%01%02+abcdefgh&port=6881&uploaded=0&downloaded=1024&left=2048&key=deadbeef&compact=1&event=started&numwant=50"
+        );
+    }
+
+    #[test]
+    fn parse_compact_announce_response() {
+        let mut buf = BytesMut::new();
+        buf.put(b"d8:completei105e10:incompletei42e8:intervali1800e".as_slice());
+        buf.put(b"12:min intervali60e".as_slice());
+        buf.put(b"5:peers12:\xC0\xA8\x01\x02\x1A\xE1\x0A\0\0\x01\xC8\xD5".as_slice());
+        buf.put(b"6:peers618:\x3F\xFF\0\0\0\0\0\0\0\0\0\0\0\0\xAB\xCD\x1A\xE1".as_slice());
+        buf.put(b"10:tracker id3:xyz15:warning message7:Careful".as_slice());
+        buf.put(b"e".as_slice());
+        let res = decode_bencode::<HttpAnnounceResponse>(&buf)
+            .unwrap()
+            .result()
+            .unwrap();
+        assert_eq!(
+            res,
+            Announcement {
+                interval: 1800,
+                min_interval: Some(60),
+                seeders: Some(105),
+                leechers: Some(42),
+                tracker_id: Some("xyz".into()),
+                warning: Some("Careful".into()),
+                peers: vec![
+                    "192.168.1.2:6881".parse::<SocketAddr>().unwrap(),
+                    "10.0.0.1:51413".parse::<SocketAddr>().unwrap(),
+                    "[3fff::abcd]:6881".parse::<SocketAddr>().unwrap(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_dict_announce_response() {
+        let mut buf = BytesMut::new();
+        buf.put(b"d8:intervali900e5:peersl".as_slice());
+        buf.put(b"d2:ip11:192.168.1.27:peer id20:-TS0000-abcdefghijkl4:porti6881ee".as_slice());
+        buf.put(b"d2:ip10:3fff::abcd4:porti51413ee".as_slice());
+        buf.put(b"d2:ip16:peer.example.com4:porti6881ee".as_slice());
+        buf.put(b"ee".as_slice());
+        let res = decode_bencode::<HttpAnnounceResponse>(&buf)
+            .unwrap()
+            .result()
+            .unwrap();
+        assert_eq!(
+            res,
+            Announcement {
+                interval: 900,
+                min_interval: None,
+                seeders: None,
+                leechers: None,
+                tracker_id: None,
+                warning: None,
+                peers: vec![
+                    "192.168.1.2:6881".parse::<SocketAddr>().unwrap(),
+                    "[3fff::abcd]:51413".parse::<SocketAddr>().unwrap(),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn parse_announce_response_bad_peers_type() {
+        let e = decode_bencode::<HttpAnnounceResponse>(b"d8:intervali1800e5:peersi3ee")
+            .unwrap_err()
+            .to_string();
+        assert!(e.contains("String or List"), "{e}");
+        assert!(e.contains("Num"), "{e}");
+    }
+
+    #[test]
+    fn parse_announce_failure_response() {
+        let res =
+            decode_bencode::<HttpAnnounceResponse>(b"d14:failure reason11:Out of bitse").unwrap();
+        assert_eq!(
+            res,
+            HttpAnnounceResponse::Failure(String::from("Out of bits"))
+        );
+    }
+
+    #[tokio::test]
+    async fn scrape_without_announce_path() {
+        let tracker = HttpTracker::try_from(
+            "http://tracker.example.com/tracker.php"
+                .parse::<Url>()
+                .unwrap(),
+        )
+        .unwrap();
+        let info_hash = "28c55196f57753c40aceb6fb58617e6995a7eddb"
+            .parse::<InfoHash>()
+            .unwrap();
+        let options = HttpOptions {
+            batch_size: NonZeroUsize::new(50).unwrap(),
+            max_url_len: 4096,
+            ..HttpOptions::default()
+        };
+        let report = tracker.scrape(&[info_hash], &options).await;
+        let e = report.get(info_hash).unwrap_err();
+        assert!(matches!(*e, TrackerError::ScrapeUnsupported));
+    }
 }
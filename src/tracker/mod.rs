@@ -1,11 +1,24 @@
+pub(crate) mod cache;
+pub(crate) mod dht;
 pub(crate) mod http;
+pub(crate) mod socks;
 pub(crate) mod udp;
+pub(crate) mod ws;
+use self::dht::*;
 use self::http::*;
 use self::udp::*;
+use self::ws::*;
 use crate::infohash::InfoHash;
 use crate::util::{PacketError, TryFromBuf};
 use bytes::Bytes;
+use clap::ValueEnum;
+use rand::random;
+use serde::{Serialize, Serializer};
 use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::sync::Arc;
+use std::time::Duration;
 use thiserror::Error;
 use url::Url;
 
@@ -13,17 +26,117 @@ use url::Url;
 pub(crate) enum Tracker {
     Http(HttpTracker),
     Udp(UdpTracker),
+    WebSocket(WsTracker),
+    Dht(DhtTracker),
 }
 
 impl Tracker {
-    pub(crate) async fn scrape(&self, hashes: &[InfoHash]) -> Result<ScrapeMap, TrackerError> {
+    /// Scrape the tracker for the given info hashes, splitting them across
+    /// multiple requests as needed.  A failed request only causes the info
+    /// hashes in that request to fail.
+    pub(crate) async fn scrape(
+        &self,
+        hashes: &[InfoHash],
+        options: &TrackerOptions,
+    ) -> ScrapeReport {
+        let mut report = match self {
+            Tracker::Http(tr) => tr.scrape(hashes, &options.http).await,
+            Tracker::Udp(tr) => tr.scrape(hashes, &options.udp).await,
+            Tracker::WebSocket(tr) => tr.scrape(hashes, &options.ws).await,
+            Tracker::Dht(tr) => tr.scrape(hashes, &options.dht).await,
+        };
+        // Trackers know v2 info hashes only by their truncated forms, so
+        // re-key any such results under the full hashes.
+        for &ih in hashes {
+            if let InfoHash::V2(_) = ih
+                && let Some(s) = report.scrapes.remove(&ih.truncated())
+            {
+                report.scrapes.insert(ih, s);
+            }
+        }
+        if options.fallback {
+            self.fallback(&mut report, hashes, options).await;
+        }
+        report
+    }
+
+    /// For each info hash that could not be scraped, try to get its numbers
+    /// of seeders & leechers from an announce request instead.  If the
+    /// announce fails as well, the original scrape error is kept.
+    async fn fallback(
+        &self,
+        report: &mut ScrapeReport,
+        hashes: &[InfoHash],
+        options: &TrackerOptions,
+    ) {
+        let mut failed = Vec::new();
+        for &ih in hashes {
+            if report.failures.contains_key(&ih) && !failed.contains(&ih) {
+                failed.push(ih);
+            }
+        }
+        if failed.is_empty() {
+            return;
+        }
+        tracing::info!(
+            tracker = %self,
+            qty = failed.len(),
+            "Scraping failed; falling back to announcing",
+        );
+        let params = AnnounceParams::fallback();
+        let results = self.announce(&failed, &params, options).await;
+        for (ih, r) in std::iter::zip(failed, results) {
+            match r.map(|ann| Scrape::from_announcement(&ann)) {
+                Ok(Some(s)) => {
+                    if let Some(msg) = &s.warning {
+                        report.warn(msg.clone());
+                    }
+                    report.failures.remove(&ih);
+                    report.scrapes.insert(ih, s);
+                }
+                Ok(None) => tracing::info!(
+                    info_hash = %ih,
+                    "Fallback announce response did not include swarm counts",
+                ),
+                Err(e) => tracing::info!(info_hash = %ih, "Fallback announce failed: {e}"),
+            }
+        }
+    }
+
+    /// Announce each of the given info hashes to the tracker in turn and
+    /// return the results in the same order
+    pub(crate) async fn announce(
+        &self,
+        hashes: &[InfoHash],
+        params: &AnnounceParams,
+        options: &TrackerOptions,
+    ) -> Vec<AnnounceResult> {
+        match self {
+            Tracker::Http(tr) => tr.announce(hashes, params, &options.http).await,
+            Tracker::Udp(tr) => tr.announce(hashes, params, &options.udp).await,
+            Tracker::WebSocket(tr) => tr.announce(hashes, params, &options.ws).await,
+            Tracker::Dht(tr) => tr.announce(hashes, params, &options.dht),
+        }
+    }
+}
+
+impl fmt::Display for Tracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Tracker::Http(tr) => tr.scrape(hashes).await,
-            Tracker::Udp(tr) => tr.scrape(hashes).await,
+            Tracker::Http(tr) => write!(f, "{tr}"),
+            Tracker::Udp(tr) => write!(f, "{tr}"),
+            Tracker::WebSocket(tr) => write!(f, "{tr}"),
+            Tracker::Dht(tr) => write!(f, "{tr}"),
         }
     }
 }
 
+impl Serialize for Tracker {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 impl std::str::FromStr for Tracker {
     type Err = TrackerUrlError;
 
@@ -32,11 +145,140 @@ impl std::str::FromStr for Tracker {
         match url.scheme() {
             "http" | "https" => Ok(Tracker::Http(HttpTracker::try_from(url)?)),
             "udp" => Ok(Tracker::Udp(UdpTracker::try_from(url)?)),
+            "ws" | "wss" => Ok(Tracker::WebSocket(WsTracker::try_from(url)?)),
             sch => Err(TrackerUrlError::UnsupportedScheme(sch.into())),
         }
     }
 }
 
+/// The client-specific fields of an announce request
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct AnnounceParams {
+    pub(crate) peer_id: [u8; 20],
+    /// A random value identifying this client across changes of IP address
+    pub(crate) key: u32,
+    pub(crate) port: u16,
+    pub(crate) uploaded: u64,
+    pub(crate) downloaded: u64,
+    pub(crate) left: u64,
+    pub(crate) event: AnnounceEvent,
+    /// Number of peers to ask for, or `None` to let the tracker decide
+    pub(crate) numwant: Option<u32>,
+}
+
+impl AnnounceParams {
+    /// Parameters for an announce made only to learn the size of a swarm:
+    /// the client asks for no peers and announces that it is stopping so
+    /// that the tracker doesn't add it to the swarm
+    pub(crate) fn fallback() -> AnnounceParams {
+        AnnounceParams {
+            peer_id: AnnounceParams::random_peer_id(),
+            key: random(),
+            port: 6881,
+            uploaded: 0,
+            downloaded: 0,
+            left: 0,
+            event: AnnounceEvent::Stopped,
+            numwant: Some(0),
+        }
+    }
+
+    /// Generate a random peer ID in the Azureus style, i.e., a client
+    /// identifier between dashes followed by random bytes
+    pub(crate) fn random_peer_id() -> [u8; 20] {
+        let mut peer_id: [u8; 20] = random();
+        peer_id[..8].copy_from_slice(b"-TS0000-");
+        peer_id
+    }
+}
+
+/// The `event` field of an announce request
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub(crate) enum AnnounceEvent {
+    /// A regular announcement made at the tracker's requested interval
+    #[default]
+    None,
+    /// The download completed
+    Completed,
+    /// The download started
+    Started,
+    /// The download stopped
+    Stopped,
+}
+
+impl AnnounceEvent {
+    /// The value of the `event` parameter in HTTP announce requests, or
+    /// `None` if the parameter should be omitted
+    fn http_value(self) -> Option<&'static str> {
+        match self {
+            AnnounceEvent::None => None,
+            AnnounceEvent::Completed => Some("completed"),
+            AnnounceEvent::Started => Some("started"),
+            AnnounceEvent::Stopped => Some("stopped"),
+        }
+    }
+}
+
+/// A tracker's response to an announce request
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub(crate) struct Announcement {
+    /// Number of seconds the tracker wants the client to wait between
+    /// regular announcements
+    pub(crate) interval: u32,
+    /// Minimum number of seconds the client must wait between announcements
+    /// (HTTP trackers only)
+    pub(crate) min_interval: Option<u32>,
+    /// Number of seeders; optional for HTTP trackers
+    pub(crate) seeders: Option<u32>,
+    /// Number of leechers; optional for HTTP trackers
+    pub(crate) leechers: Option<u32>,
+    /// A value that the client should send back in subsequent announcements
+    /// (HTTP trackers only)
+    pub(crate) tracker_id: Option<String>,
+    /// A warning from the tracker (HTTP trackers only)
+    pub(crate) warning: Option<String>,
+    pub(crate) peers: Vec<SocketAddr>,
+}
+
+/// The outcome of announcing a single info hash to a tracker
+pub(crate) type AnnounceResult = Result<Announcement, Arc<TrackerError>>;
+
+/// Settings for communicating with trackers
+#[derive(Clone, Debug, Default)]
+pub(crate) struct TrackerOptions {
+    pub(crate) http: HttpOptions,
+    pub(crate) udp: UdpOptions,
+    pub(crate) ws: WsOptions,
+    pub(crate) dht: DhtOptions,
+    /// Whether to fall back to announcing for info hashes that could not be
+    /// scraped
+    pub(crate) fallback: bool,
+}
+
+/// An IP address family to which communication with trackers is restricted
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    fn includes(self, addr: &SocketAddr) -> bool {
+        match self {
+            IpFamily::V4 => addr.is_ipv4(),
+            IpFamily::V6 => addr.is_ipv6(),
+        }
+    }
+
+    /// Return the unspecified address of this family, for binding to
+    fn unspecified(self) -> IpAddr {
+        match self {
+            IpFamily::V4 => Ipv4Addr::UNSPECIFIED.into(),
+            IpFamily::V6 => Ipv6Addr::UNSPECIFIED.into(),
+        }
+    }
+}
+
 #[derive(Clone, Debug, Error, Eq, PartialEq)]
 pub(crate) enum TrackerUrlError {
     #[error("invalid tracker URL")]
@@ -45,19 +287,118 @@ pub(crate) enum TrackerUrlError {
     UnsupportedScheme(String),
     #[error("no host in tracker URL")]
     NoHost,
-    #[error("no \"announce\" string in HTTP tracker URL path")]
-    NoAnnounce,
     #[error("no port in UDP tracker URL")]
     NoUdpPort,
 }
 
 pub(crate) type ScrapeMap = HashMap<InfoHash, Scrape>;
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// The outcome of scraping a tracker for a single info hash: either the
+/// tracker's statistics (`None` if the tracker isn't tracking the info hash)
+/// or the error that occurred while making the request for it
+pub(crate) type ScrapeResult = Result<Option<Scrape>, Arc<TrackerError>>;
+
+/// The outcomes of scraping a tracker for a list of info hashes
+#[derive(Debug, Default)]
+pub(crate) struct ScrapeReport {
+    scrapes: ScrapeMap,
+    /// Info hashes whose requests failed, mapped to the errors.  All of the
+    /// info hashes in a failed request share the same error.
+    failures: HashMap<InfoHash, Arc<TrackerError>>,
+    /// Distinct warning messages sent by the tracker, in the order received
+    warnings: Vec<String>,
+}
+
+impl ScrapeReport {
+    /// Record the result of a request for the given info hashes
+    fn record(&mut self, hashes: &[InfoHash], r: Result<ScrapeMap, TrackerError>) {
+        match r {
+            Ok(scrapes) => self.scrapes.extend(scrapes),
+            Err(e) => self.fail(hashes, e),
+        }
+    }
+
+    /// Mark all of the given info hashes as failed with the given error
+    fn fail(&mut self, hashes: &[InfoHash], e: TrackerError) {
+        let e = Arc::new(e);
+        for &ih in hashes {
+            self.failures.insert(ih, Arc::clone(&e));
+        }
+    }
+
+    /// Record a warning message sent by the tracker
+    fn warn(&mut self, msg: String) {
+        if !self.warnings.contains(&msg) {
+            self.warnings.push(msg);
+        }
+    }
+
+    /// Return the warning messages sent by the tracker, whether or not they
+    /// accompanied statistics for any of the info hashes
+    pub(crate) fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+
+    /// Return the outcome for the given info hash
+    pub(crate) fn get(&self, info_hash: InfoHash) -> ScrapeResult {
+        match self.failures.get(&info_hash) {
+            Some(e) => Err(Arc::clone(e)),
+            None => Ok(self.scrapes.get(&info_hash).cloned()),
+        }
+    }
+}
+
+/// Run a request future, failing with `TrackerError::Timeout` if it does not
+/// complete within `limit`
+async fn with_timeout<T, F>(limit: Duration, fut: F) -> Result<T, TrackerError>
+where
+    F: Future<Output = Result<T, TrackerError>>,
+{
+    tokio::time::timeout(limit, fut)
+        .await
+        .unwrap_or(Err(TrackerError::Timeout))
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
 pub(crate) struct Scrape {
-    pub(crate) complete: u32,
-    pub(crate) incomplete: u32,
-    pub(crate) downloaded: u32,
+    /// Number of seeders, or `None` if unknown (only possible when parsing
+    /// leniently)
+    pub(crate) complete: Option<u32>,
+    /// Number of leechers, or `None` if unknown (only possible when parsing
+    /// leniently)
+    pub(crate) incomplete: Option<u32>,
+    /// Number of completed downloads, or `None` if unknown (as when the
+    /// statistics were obtained from an announce rather than a scrape)
+    pub(crate) downloaded: Option<u32>,
+    /// Whether the numbers are estimates derived from DHT bloom filters
+    /// rather than counts reported by a tracker
+    pub(crate) estimated: bool,
+    /// Minimum number of seconds the tracker wants clients to wait between
+    /// scrape requests (BEP 48 `flags.min_request_interval`; HTTP trackers
+    /// only)
+    pub(crate) min_request_interval: Option<u32>,
+    /// A warning from the tracker (HTTP trackers only)
+    pub(crate) warning: Option<String>,
+    /// The torrent's name as reported by the tracker (HTTP trackers only)
+    pub(crate) name: Option<String>,
+    /// Number of active downloaders, not counting partial seeds ([BEP 21]
+    /// `downloaders`; HTTP trackers only)
+    ///
+    /// [BEP 21]: https://www.bittorrent.org/beps/bep_0021.html
+    pub(crate) downloaders: Option<u32>,
+}
+
+impl Scrape {
+    /// Derive swarm statistics from an announce response.  Returns `None` if
+    /// the response does not include the numbers of seeders and leechers.
+    pub(crate) fn from_announcement(ann: &Announcement) -> Option<Scrape> {
+        Some(Scrape {
+            complete: Some(ann.seeders?),
+            incomplete: Some(ann.leechers?),
+            downloaded: None,
+            ..Scrape::default()
+        })
+    }
 }
 
 impl TryFromBuf for Scrape {
@@ -66,19 +407,94 @@ impl TryFromBuf for Scrape {
         let completed = u32::try_from_buf(buf)?;
         let leechers = u32::try_from_buf(buf)?;
         Ok(Scrape {
-            complete: seeders,
-            incomplete: leechers,
-            downloaded: completed,
+            complete: Some(seeders),
+            incomplete: Some(leechers),
+            downloaded: Some(completed),
+            ..Scrape::default()
         })
     }
 }
 
+impl TryFromBuf for SocketAddrV4 {
+    fn try_from_buf(buf: &mut Bytes) -> Result<Self, PacketError> {
+        let ip = Ipv4Addr::from(u32::try_from_buf(buf)?);
+        let port = u16::try_from_buf(buf)?;
+        Ok(SocketAddrV4::new(ip, port))
+    }
+}
+
+impl TryFromBuf for SocketAddrV6 {
+    fn try_from_buf(buf: &mut Bytes) -> Result<Self, PacketError> {
+        let ip = Ipv6Addr::from(u128::try_from_buf(buf)?);
+        let port = u16::try_from_buf(buf)?;
+        Ok(SocketAddrV6::new(ip, port, 0, 0))
+    }
+}
+
 #[derive(Debug, Error)]
 pub(crate) enum TrackerError {
     #[error("tracker replied with error message {0:?}")]
     Failure(String),
+    #[error("tracker did not respond in time")]
+    Timeout,
+    #[error("tracker does not support scraping")]
+    ScrapeUnsupported,
     #[error(transparent)]
     Http(#[from] HttpTrackerError),
     #[error(transparent)]
     Udp(#[from] UdpTrackerError),
+    #[error(transparent)]
+    WebSocket(#[from] WsTrackerError),
+    #[error(transparent)]
+    Dht(#[from] DhtError),
+}
+
+impl TrackerError {
+    /// A short machine-readable name for the category of error: "failure"
+    /// (the tracker replied with an error message), "timeout", "unsupported",
+    /// "network", "http-status", "rate-limited" (the tracker's minimum request
+    /// interval is longer than the timeout), or "protocol" (the tracker's
+    /// reply could not be parsed or did not make sense)
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            TrackerError::Failure(_) => "failure",
+            TrackerError::Timeout => "timeout",
+            TrackerError::ScrapeUnsupported => "unsupported",
+            TrackerError::Http(e) => e.kind(),
+            TrackerError::Udp(e) => e.kind(),
+            TrackerError::WebSocket(e) => e.kind(),
+            TrackerError::Dht(e) => e.kind(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_failed_batch() {
+        let hashes = [
+            "28c55196f57753c40aceb6fb58617e6995a7eddb",
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            "b851474b74f65cd19f981c723590e3e520242b97",
+        ]
+        .map(|s| s.parse::<InfoHash>().unwrap());
+        let scrape = Scrape {
+            complete: Some(10),
+            incomplete: Some(0),
+            downloaded: Some(32),
+            ..Scrape::default()
+        };
+        let mut report = ScrapeReport::default();
+        report.record(
+            &hashes[..2],
+            Ok(ScrapeMap::from([(hashes[0], scrape.clone())])),
+        );
+        report.record(&hashes[2..], Err(TrackerError::Timeout));
+        assert_eq!(report.get(hashes[0]).unwrap(), Some(scrape));
+        assert_eq!(report.get(hashes[1]).unwrap(), None);
+        let e = report.get(hashes[2]).unwrap_err();
+        assert!(matches!(*e, TrackerError::Timeout));
+    }
 }
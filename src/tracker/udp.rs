@@ -1,10 +1,18 @@
-use super::{Scrape, ScrapeMap, TrackerError, TrackerUrlError};
+use super::cache::ConnectionCache;
+use super::socks::{SocksError, SocksProxy, SocksTarget, SocksUdpSocket};
+use super::{
+    AnnounceEvent, AnnounceParams, AnnounceResult, Announcement, IpFamily, Scrape, ScrapeMap,
+    ScrapeReport, TrackerError, TrackerUrlError, with_timeout,
+};
 use crate::infohash::InfoHash;
 use crate::util::{PacketError, TryBytes};
 use bytes::{BufMut, Bytes, BytesMut};
+use futures_util::stream::{FuturesUnordered, StreamExt};
 use rand::random;
 use std::fmt;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::Arc;
 use std::time::Duration;
 use thiserror::Error;
 use tokio::net::{UdpSocket, lookup_host};
@@ -17,19 +25,147 @@ const UDP_PACKET_LEN: usize = 65535;
 
 const PROTOCOL_ID: u64 = 0x41727101980;
 const CONNECT_ACTION: u32 = 0;
+const ANNOUNCE_ACTION: u32 = 1;
 const SCRAPE_ACTION: u32 = 2;
 const ERROR_ACTION: u32 = 3;
 
+/// How long to wait for a connection attempt to one of a tracker's addresses
+/// to succeed before also trying the next address (the "Connection Attempt
+/// Delay" of RFC 8305)
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub(crate) struct UdpTracker(UdpUrl);
 
+/// Settings for communicating with UDP trackers
+#[derive(Clone, Debug)]
+pub(crate) struct UdpOptions {
+    /// Maximum number of info hashes to send in a single scrape request.  BEP
+    /// 15 notes that about 74 info hashes fit in one packet.
+    pub(crate) batch_size: NonZeroUsize,
+    /// Maximum amount of time to wait for each request (including
+    /// connecting, if needed) to complete
+    pub(crate) timeout: Duration,
+    /// Whether to send the path & query string of the tracker URL in scrape
+    /// & announce requests (BEP 41)
+    pub(crate) url_data: bool,
+    /// If set, only communicate with trackers over this IP address family
+    pub(crate) family: Option<IpFamily>,
+    /// When to retransmit requests that the tracker has not replied to
+    pub(crate) retry: RetryPolicy,
+    /// How long a connection ID may be used for after it is obtained.  BEP 15
+    /// specifies one minute.
+    pub(crate) connection_lifetime: Duration,
+    /// Cache in which to look up & store connection IDs
+    pub(crate) connection_cache: Option<Arc<ConnectionCache>>,
+    /// SOCKS5 proxy through which to relay all communication with trackers
+    pub(crate) proxy: Option<SocksProxy>,
+}
+
+impl Default for UdpOptions {
+    fn default() -> UdpOptions {
+        UdpOptions {
+            batch_size: NonZeroUsize::new(74).expect("74 should be nonzero"),
+            timeout: Duration::from_secs(30),
+            url_data: true,
+            family: None,
+            retry: RetryPolicy::default(),
+            connection_lifetime: Duration::from_secs(60),
+            connection_cache: None,
+            proxy: None,
+        }
+    }
+}
+
+/// Settings for retransmitting UDP tracker requests that go unanswered.  The
+/// defaults are those specified by BEP 15.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct RetryPolicy {
+    /// How long to wait for a reply to the first transmission of a request
+    pub(crate) initial_timeout: Duration,
+    /// Factor by which the wait for a reply is multiplied after each
+    /// retransmission
+    pub(crate) backoff: NonZeroU32,
+    /// Maximum number of times to retransmit a request before giving up
+    pub(crate) max_retries: u32,
+}
+
+impl RetryPolicy {
+    /// Return how long to wait for a reply after the given number of
+    /// retransmissions
+    fn wait(&self, retries: u32) -> Duration {
+        self.initial_timeout
+            .saturating_mul(self.backoff.get().saturating_pow(retries))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            initial_timeout: Duration::from_secs(15),
+            backoff: NonZeroU32::new(2).expect("2 should be nonzero"),
+            max_retries: 8,
+        }
+    }
+}
+
 impl UdpTracker {
     #[tracing::instrument(name = "scrape-udp", skip_all, fields(tracker = %self.0))]
-    pub(crate) async fn scrape(&self, hashes: &[InfoHash]) -> Result<ScrapeMap, TrackerError> {
-        UdpTrackerSession::new(&self.0.host, self.0.port)
-            .await?
-            .scrape(hashes)
-            .await
+    pub(crate) async fn scrape(&self, hashes: &[InfoHash], options: &UdpOptions) -> ScrapeReport {
+        let mut report = ScrapeReport::default();
+        // All batches share a session so that they can reuse the same
+        // connection ID.
+        let mut session = match with_timeout(options.timeout, self.open_session(options)).await {
+            Ok(session) => session,
+            Err(e) => {
+                report.fail(hashes, e);
+                return report;
+            }
+        };
+        for batch in hashes.chunks(options.batch_size.get()) {
+            let r = with_timeout(options.timeout, session.scrape(batch)).await;
+            report.record(batch, r);
+        }
+        report
+    }
+
+    /// Announce each of the given info hashes to the tracker in turn and
+    /// return the results in the same order
+    #[tracing::instrument(name = "announce-udp", skip_all, fields(tracker = %self.0))]
+    pub(crate) async fn announce(
+        &self,
+        hashes: &[InfoHash],
+        params: &AnnounceParams,
+        options: &UdpOptions,
+    ) -> Vec<AnnounceResult> {
+        let mut session = match with_timeout(options.timeout, self.open_session(options)).await {
+            Ok(session) => session,
+            Err(e) => {
+                let e = Arc::new(e);
+                return hashes.iter().map(|_| Err(Arc::clone(&e))).collect();
+            }
+        };
+        let mut results = Vec::with_capacity(hashes.len());
+        for &ih in hashes {
+            let r = with_timeout(options.timeout, session.announce(ih, params)).await;
+            results.push(r.map_err(Arc::new));
+        }
+        results
+    }
+
+    async fn open_session(&self, options: &UdpOptions) -> Result<UdpTrackerSession, TrackerError> {
+        let extensions = if options.url_data {
+            encode_url_data(&self.0.urldata)
+        } else {
+            Bytes::new()
+        };
+        UdpTrackerSession::new(&self.0.host, self.0.port, extensions, options).await
+    }
+}
+
+impl fmt::Display for UdpTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
     }
 }
 
@@ -93,19 +229,174 @@ impl TryFrom<Url> for UdpUrl {
 
 #[derive(Debug)]
 struct UdpTrackerSession {
-    socket: UdpSocket,
+    socket: Transport,
+    /// The address of the tracker, if known.  When a SOCKS proxy resolves the
+    /// tracker's hostname, this is only learned from the tracker's first
+    /// reply, and only if the proxy reports it.
+    peer: Option<SocketAddr>,
+    /// Whether the tracker is being communicated with over IPv6, in which
+    /// case peers in announce responses are IPv6 addresses
+    ipv6: bool,
+    /// Encoded BEP 41 options to append to scrape & announce requests
+    extensions: Bytes,
     conn: Option<ConnectionInfo>,
+    retry: RetryPolicy,
+    connection_lifetime: Duration,
+    cache: Option<Arc<ConnectionCache>>,
+    /// All of the addresses that the tracker's hostname resolved to, for
+    /// finding another to use if a cached connection ID gets no reply.  This
+    /// is empty when communicating through a SOCKS proxy.
+    candidates: Vec<SocketAddr>,
+    /// Number of times the current request has been retransmitted so far,
+    /// including retransmissions of any connection requests made along the
+    /// way
+    retries: u32,
 }
 
 impl UdpTrackerSession {
-    async fn new(host: &str, port: u16) -> Result<Self, UdpTrackerError> {
-        let Some(addr) = lookup_host((host, port))
+    /// Resolve the tracker's hostname, optionally restricted to a given
+    /// address family, and connect to it
+    async fn new(
+        host: &str,
+        port: u16,
+        extensions: Bytes,
+        options: &UdpOptions,
+    ) -> Result<Self, TrackerError> {
+        if let Some(proxy) = &options.proxy {
+            return Self::connect_proxied(proxy, host, port, extensions, options).await;
+        }
+        let addrs = resolve(host, port, options).await?;
+        Self::connect_any(addrs, extensions, options).await
+    }
+
+    /// Connect to the tracker through a SOCKS5 proxy.  If the proxy is to
+    /// resolve hostnames, the tracker's hostname is passed to it as-is;
+    /// otherwise, only the most preferred of the tracker's addresses is
+    /// tried, as the proxy cannot race connection attempts.
+    async fn connect_proxied(
+        proxy: &SocksProxy,
+        host: &str,
+        port: u16,
+        extensions: Bytes,
+        options: &UdpOptions,
+    ) -> Result<Self, TrackerError> {
+        let target = if let Ok(ip) = host.parse::<IpAddr>() {
+            SocksTarget::Addr(SocketAddr::new(ip, port))
+        } else if proxy.remote_dns {
+            SocksTarget::Domain(host.to_owned(), port)
+        } else {
+            let addr = sort_addresses(resolve(host, port, options).await?)
+                .into_iter()
+                .next()
+                .ok_or(UdpTrackerError::NoResolve)?;
+            SocksTarget::Addr(addr)
+        };
+        let socket = SocksUdpSocket::associate(proxy, &target)
             .await
-            .map_err(UdpTrackerError::Lookup)?
-            .next()
-        else {
-            return Err(UdpTrackerError::NoResolve);
+            .map_err(UdpTrackerError::from)?;
+        tracing::info!(%proxy, "Relaying tracker traffic through SOCKS proxy");
+        let peer = match target {
+            SocksTarget::Addr(addr) => Some(addr),
+            SocksTarget::Domain(..) => None,
         };
+        let mut session = UdpTrackerSession {
+            socket: Transport::Socks(socket),
+            peer,
+            ipv6: peer.is_some_and(|addr| addr.is_ipv6()),
+            extensions,
+            conn: None,
+            retry: options.retry,
+            connection_lifetime: options.connection_lifetime,
+            cache: options.connection_cache.clone(),
+            candidates: Vec::new(),
+            retries: 0,
+        };
+        session.get_connection().await?;
+        Ok(session)
+    }
+
+    /// Connect to the tracker at one of the given addresses in the manner of
+    /// RFC 8305 ("Happy Eyeballs"), preferring IPv6: each address is sent a
+    /// connection request in turn, with the next address tried whenever an
+    /// attempt fails or has gone unanswered for `CONNECTION_ATTEMPT_DELAY`,
+    /// and the session uses the first address to answer.  If one of the
+    /// addresses has a cached connection ID, no handshake is needed, and that
+    /// address is used without contacting the tracker; the other addresses
+    /// are only tried if a request with the cached ID goes unanswered.
+    async fn connect_any(
+        addrs: Vec<SocketAddr>,
+        extensions: Bytes,
+        options: &UdpOptions,
+    ) -> Result<Self, TrackerError> {
+        let addrs = sort_addresses(addrs);
+        if let Some(cache) = &options.connection_cache
+            && let Some(&addr) = addrs.iter().find(|&&addr| cache.contains(addr))
+        {
+            let mut session = Self::open(addr, extensions, options).await?;
+            session.get_connection().await?;
+            session.candidates = addrs;
+            return Ok(session);
+        }
+        let mut pending = addrs.clone().into_iter();
+        let mut attempts = FuturesUnordered::new();
+        let mut last_error = None;
+        loop {
+            if attempts.is_empty() {
+                match pending.next() {
+                    Some(addr) => attempts.push(Self::attempt(addr, extensions.clone(), options)),
+                    None => {
+                        return Err(last_error.unwrap_or_else(|| UdpTrackerError::NoResolve.into()));
+                    }
+                }
+            }
+            tokio::select! {
+                Some(r) = attempts.next() => match r {
+                    Ok(mut session) => {
+                        session.candidates = addrs;
+                        return Ok(session);
+                    }
+                    Err(e) => {
+                        tracing::info!("Connection attempt failed: {e}");
+                        last_error = Some(e);
+                        if let Some(addr) = pending.next() {
+                            attempts.push(Self::attempt(addr, extensions.clone(), options));
+                        }
+                    }
+                },
+                () = tokio::time::sleep(CONNECTION_ATTEMPT_DELAY), if pending.len() > 0 => {
+                    if let Some(addr) = pending.next() {
+                        attempts.push(Self::attempt(addr, extensions.clone(), options));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Open a socket to the given address of the tracker and perform the
+    /// BEP 15 connection handshake over it, even if a connection ID for the
+    /// address is cached
+    async fn attempt(
+        addr: SocketAddr,
+        extensions: Bytes,
+        options: &UdpOptions,
+    ) -> Result<Self, TrackerError> {
+        let mut session = Self::open(addr, extensions, options).await?;
+        session.handshake().await?;
+        tracing::info!(
+            remote_ip = %addr.ip(),
+            remote_port = addr.port(),
+            "Tracker answered at address",
+        );
+        Ok(session)
+    }
+
+    /// Open a socket to the given address of the tracker without sending
+    /// anything over it
+    async fn open(
+        addr: SocketAddr,
+        extensions: Bytes,
+        options: &UdpOptions,
+    ) -> Result<Self, TrackerError> {
         let bindaddr = match addr {
             SocketAddr::V4(_) => "0.0.0.0:0",
             SocketAddr::V6(_) => "[::]:0",
@@ -113,56 +404,141 @@ impl UdpTrackerSession {
         let socket = UdpSocket::bind(bindaddr)
             .await
             .map_err(UdpTrackerError::Bind)?;
-        tracing::info!(
-            remote_host = host,
-            remote_ip = %addr.ip(),
-            remote_port = port,
-            "Connected UDP socket to remote tracker port",
-        );
         socket
             .connect(addr)
             .await
             .map_err(UdpTrackerError::Connect)?;
-        Ok(UdpTrackerSession { socket, conn: None })
+        tracing::info!(
+            remote_ip = %addr.ip(),
+            remote_port = addr.port(),
+            "Trying tracker address",
+        );
+        Ok(UdpTrackerSession {
+            socket: Transport::Direct(socket),
+            peer: Some(addr),
+            ipv6: addr.is_ipv6(),
+            extensions,
+            conn: None,
+            retry: options.retry,
+            connection_lifetime: options.connection_lifetime,
+            cache: options.connection_cache.clone(),
+            candidates: Vec::new(),
+            retries: 0,
+        })
     }
 
     async fn scrape(&mut self, hashes: &[InfoHash]) -> Result<ScrapeMap, TrackerError> {
+        tracing::info!("Sending scrape request to tracker");
+        let extensions = self.extensions.clone();
+        let resp = self
+            .request(
+                |connection_id, transaction_id| {
+                    Bytes::from(UdpScrapeRequest {
+                        connection_id,
+                        transaction_id,
+                        info_hashes: hashes,
+                        extensions: &extensions,
+                    })
+                },
+                UdpScrapeResponse::try_from,
+            )
+            .await?;
+        tracing::info!("Received scrape response from tracker");
+        if hashes.len() != resp.scrapes.len() {
+            Err(UdpTrackerError::ScrapeLenMismatch {
+                expected: hashes.len(),
+                got: resp.scrapes.len(),
+            }
+            .into())
+        } else {
+            Ok(std::iter::zip(hashes.to_vec(), resp.scrapes).collect())
+        }
+    }
+
+    async fn announce(
+        &mut self,
+        info_hash: InfoHash,
+        params: &AnnounceParams,
+    ) -> Result<Announcement, TrackerError> {
+        tracing::info!("Sending announce request to tracker");
+        let ipv6 = self.ipv6;
+        let extensions = self.extensions.clone();
+        let resp = self
+            .request(
+                |connection_id, transaction_id| {
+                    Bytes::from(UdpAnnounceRequest {
+                        connection_id,
+                        transaction_id,
+                        info_hash,
+                        params,
+                        extensions: &extensions,
+                    })
+                },
+                |buf| UdpAnnounceResponse::from_bytes(buf, ipv6),
+            )
+            .await?;
+        tracing::info!("Received announce response from tracker");
+        Ok(resp.announcement)
+    }
+
+    /// Send a request message — constructed by `build` from a connection ID
+    /// and transaction ID — to the tracker, connecting first if necessary,
+    /// and parse the response with `parse`.  If the connection expires before
+    /// a response is received, the request is retried with a new connection.
+    async fn request<T, B, P>(&mut self, build: B, parse: P) -> Result<T, TrackerError>
+    where
+        B: Fn(u64, u32) -> Bytes,
+        P: Fn(Bytes) -> Result<T, UdpTrackerError>,
+    {
+        self.retries = 0;
         loop {
             let conn = self.get_connection().await?;
             let transaction_id = self.make_transaction_id();
-            tracing::info!("Sending scrape request to tracker");
-            let msg = Bytes::from(UdpScrapeRequest {
-                connection_id: conn.id,
-                transaction_id,
-                info_hashes: hashes,
-            });
-            match timeout_at(conn.expiration, self.chat(msg)).await {
+            let msg = build(conn.id, transaction_id);
+            let deadline = if conn.cached {
+                // Trackers may silently ignore requests with connection IDs
+                // they don't recognize, so don't wait on a cached connection
+                // ID any longer than the first retransmission.
+                conn.expiration
+                    .min(Instant::now() + self.retry.wait(self.retries))
+            } else {
+                conn.expiration
+            };
+            match timeout_at(deadline, self.chat(msg, transaction_id)).await {
                 Ok(Ok(buf)) => {
-                    tracing::info!("Received scrape response from tracker");
-                    let resp = Response::<UdpScrapeResponse>::from_bytes(
-                        buf,
-                        UdpScrapeResponse::try_from,
-                    )?
-                    .ok()?;
-                    if resp.transaction_id != transaction_id {
-                        return Err(UdpTrackerError::XactionMismatch {
-                            expected: transaction_id,
-                            got: resp.transaction_id,
+                    let resp = match Response::<T>::from_bytes(buf, &parse)? {
+                        // There's no standard error message for an
+                        // unrecognized connection ID, so any error in reply to
+                        // a cached one is taken as a possible rejection.
+                        Response::Failure(msg) if conn.cached => {
+                            tracing::info!(
+                                "Tracker rejected cached connection ID: {msg}; reconnecting"
+                            );
+                            self.discard_connection();
+                            continue;
                         }
-                        .into());
-                    } else if hashes.len() != resp.scrapes.len() {
-                        return Err(UdpTrackerError::ScrapeLenMismatch {
-                            expected: hashes.len(),
-                            got: resp.scrapes.len(),
-                        }
-                        .into());
-                    } else {
-                        return Ok(std::iter::zip(hashes.to_vec(), resp.scrapes).collect());
-                    }
+                        r => r.ok()?,
+                    };
+                    return Ok(resp);
                 }
                 Ok(Err(e)) => return Err(e.into()),
+                Err(_) if conn.cached => {
+                    tracing::info!(
+                        "Tracker did not reply to request with cached connection ID; reconnecting"
+                    );
+                    self.discard_connection();
+                    Box::pin(self.reconnect_any()).await?;
+                    continue;
+                }
+                // The connection expired mid-wait, which counts as one more
+                // retransmission so that a tracker that only answers
+                // connection requests can't keep us reconnecting forever.
+                Err(_) if self.retries >= self.retry.max_retries => {
+                    return Err(UdpTrackerError::NoReply(self.retries).into());
+                }
                 Err(_) => {
                     tracing::info!("Connection to tracker timed out; restarting");
+                    self.retries += 1;
                     self.reset_connection();
                     continue;
                 }
@@ -177,58 +553,104 @@ impl UdpTrackerSession {
             } else {
                 tracing::info!("Connection to tracker expired; will reconnect");
             }
+        } else if let Some((id, expiration)) = self
+            .cache
+            .as_ref()
+            .zip(self.peer)
+            .and_then(|(c, peer)| c.get(peer))
+        {
+            tracing::info!("Using cached connection ID for tracker");
+            let conn = ConnectionInfo {
+                id,
+                expiration,
+                cached: true,
+            };
+            self.conn = Some(conn);
+            return Ok(conn);
         }
+        self.handshake().await
+    }
+
+    /// Obtain a new connection ID from the tracker and cache it
+    async fn handshake(&mut self) -> Result<ConnectionInfo, TrackerError> {
         let conn = self.connect().await?;
+        if let (Some(cache), Some(peer)) = (&self.cache, self.peer) {
+            cache.insert(peer, conn.id, conn.expiration);
+        }
         self.conn = Some(conn);
         Ok(conn)
     }
 
+    /// Connect afresh to whichever of the tracker's addresses answers first,
+    /// as the address in use may no longer be reachable.  When there are no
+    /// other addresses to try, the next request simply reconnects to the
+    /// current one.
+    async fn reconnect_any(&mut self) -> Result<(), TrackerError> {
+        if self.candidates.is_empty() {
+            return Ok(());
+        }
+        let options = UdpOptions {
+            retry: self.retry,
+            connection_lifetime: self.connection_lifetime,
+            connection_cache: self.cache.clone(),
+            ..UdpOptions::default()
+        };
+        let fresh =
+            Self::connect_any(self.candidates.clone(), self.extensions.clone(), &options).await?;
+        self.socket = fresh.socket;
+        self.peer = fresh.peer;
+        self.ipv6 = fresh.ipv6;
+        self.conn = fresh.conn;
+        Ok(())
+    }
+
     fn reset_connection(&mut self) {
         self.conn = None;
     }
 
-    async fn connect(&self) -> Result<ConnectionInfo, TrackerError> {
+    /// Forget the current connection ID and remove it from the cache so that
+    /// the next request makes a fresh handshake
+    fn discard_connection(&mut self) {
+        if let (Some(cache), Some(peer)) = (&self.cache, self.peer) {
+            cache.remove(peer);
+        }
+        self.conn = None;
+    }
+
+    async fn connect(&mut self) -> Result<ConnectionInfo, TrackerError> {
         tracing::info!("Sending connection request to tracker");
         let transaction_id = self.make_transaction_id();
         let msg = Bytes::from(UdpConnectionRequest { transaction_id });
-        let raw_resp = self.chat(msg).await?;
-        // TODO: Should communication be retried on parse errors and mismatched
-        // transaction IDs?
+        let raw_resp = self.chat(msg, transaction_id).await?;
         let resp = Response::<UdpConnectionResponse>::from_bytes(
             raw_resp,
             UdpConnectionResponse::try_from,
         )?
         .ok()?;
-        if resp.transaction_id != transaction_id {
-            return Err(UdpTrackerError::XactionMismatch {
-                expected: transaction_id,
-                got: resp.transaction_id,
-            }
-            .into());
-        }
         tracing::info!("Connected to tracker");
-        let expiration = Instant::now() + Duration::from_secs(60);
+        let expiration = Instant::now() + self.connection_lifetime;
         Ok(ConnectionInfo {
             id: resp.connection_id,
             expiration,
+            cached: false,
         })
     }
 
-    async fn chat(&self, msg: Bytes) -> Result<Bytes, UdpTrackerError> {
-        let mut n = 0;
+    /// Send a message with the given transaction ID to the tracker and return
+    /// its reply, retransmitting the message according to the session's retry
+    /// policy.  The count of retransmissions carries over between calls made
+    /// for the same request.
+    async fn chat(&mut self, msg: Bytes, transaction_id: u32) -> Result<Bytes, UdpTrackerError> {
         loop {
             self.send(&msg).await?;
-            let maxtime = Duration::from_secs(15 << n);
-            if let Ok(r) = timeout(maxtime, self.recv()).await {
+            let maxtime = self.retry.wait(self.retries);
+            if let Ok(r) = timeout(maxtime, self.recv_reply(transaction_id)).await {
                 return r;
+            } else if self.retries >= self.retry.max_retries {
+                return Err(UdpTrackerError::NoReply(self.retries));
             } else {
                 tracing::info!("Tracker did not reply in time; resending message");
-                if n < 8 {
-                    // TODO: This count should remember timeouts from previous
-                    // connections & connection attempts
-                    n += 1;
-                }
-                continue;
+                self.retries += 1;
             }
         }
     }
@@ -238,17 +660,116 @@ impl UdpTrackerSession {
     }
 
     async fn send(&self, msg: &Bytes) -> Result<(), UdpTrackerError> {
-        self.socket.send(msg).await.map_err(UdpTrackerError::Send)?;
-        Ok(())
+        self.socket.send(msg).await.map_err(UdpTrackerError::Send)
     }
 
-    async fn recv(&self) -> Result<Bytes, UdpTrackerError> {
-        let mut buf = BytesMut::with_capacity(UDP_PACKET_LEN);
-        self.socket
-            .recv_buf(&mut buf)
-            .await
-            .map_err(UdpTrackerError::Recv)?;
-        Ok(buf.freeze())
+    /// Receive packets until one arrives from the tracker in reply to the
+    /// transaction with the given ID.  Any other packets — such as late
+    /// replies to earlier transactions — are logged & discarded.
+    async fn recv_reply(&mut self, transaction_id: u32) -> Result<Bytes, UdpTrackerError> {
+        loop {
+            let (buf, sender) = self.recv().await?;
+            if let (Some(peer), Some(sender)) = (self.peer, sender)
+                && sender != peer
+            {
+                tracing::info!(%sender, "Ignoring UDP packet from unexpected address");
+                continue;
+            }
+            match reply_transaction_id(&buf) {
+                Some(id) if id == transaction_id => {
+                    if self.peer.is_none()
+                        && let Some(sender) = sender
+                    {
+                        tracing::info!(%sender, "SOCKS proxy resolved tracker to address");
+                        self.peer = Some(sender);
+                        self.ipv6 = sender.is_ipv6();
+                    }
+                    return Ok(buf);
+                }
+                Some(id) => tracing::info!(
+                    expected = format_args!("{transaction_id:#x}"),
+                    got = format_args!("{id:#x}"),
+                    "Ignoring UDP packet for a different transaction",
+                ),
+                None => tracing::info!(
+                    len = buf.len(),
+                    "Ignoring UDP packet too short to be a tracker response",
+                ),
+            }
+        }
+    }
+
+    async fn recv(&self) -> Result<(Bytes, Option<SocketAddr>), UdpTrackerError> {
+        self.socket.recv().await.map_err(UdpTrackerError::Recv)
+    }
+}
+
+/// The means by which a session exchanges packets with a tracker
+#[derive(Debug)]
+enum Transport {
+    /// A UDP socket connected directly to the tracker
+    Direct(UdpSocket),
+    /// An association with a SOCKS5 proxy that relays packets to & from the
+    /// tracker
+    Socks(SocksUdpSocket),
+}
+
+impl Transport {
+    async fn send(&self, msg: &[u8]) -> std::io::Result<()> {
+        match self {
+            Transport::Direct(socket) => socket.send(msg).await.map(|_| ()),
+            Transport::Socks(socket) => socket.send(msg).await,
+        }
+    }
+
+    /// Receive a packet along with the address it was sent from, if known
+    async fn recv(&self) -> std::io::Result<(Bytes, Option<SocketAddr>)> {
+        match self {
+            Transport::Direct(socket) => {
+                let mut buf = BytesMut::with_capacity(UDP_PACKET_LEN);
+                let (_, sender) = socket.recv_buf_from(&mut buf).await?;
+                Ok((buf.freeze(), Some(sender)))
+            }
+            Transport::Socks(socket) => socket.recv().await,
+        }
+    }
+}
+
+/// Resolve a tracker's hostname, keeping only addresses in the address
+/// family selected by `options`, if any
+async fn resolve(
+    host: &str,
+    port: u16,
+    options: &UdpOptions,
+) -> Result<Vec<SocketAddr>, UdpTrackerError> {
+    Ok(lookup_host((host, port))
+        .await
+        .map_err(UdpTrackerError::Lookup)?
+        .filter(|addr| options.family.is_none_or(|f| f.includes(addr)))
+        .collect())
+}
+
+/// Return the transaction ID of a response packet from a UDP tracker, which
+/// is found at the same position in every type of response, or `None` if the
+/// packet is too short
+fn reply_transaction_id(buf: &[u8]) -> Option<u32> {
+    let bs = buf.get(4..8)?;
+    Some(u32::from_be_bytes(bs.try_into().ok()?))
+}
+
+/// Order a tracker's resolved addresses for connection attempts per RFC 8305:
+/// alternating between families, starting with IPv6, and otherwise keeping
+/// the resolver's order
+fn sort_addresses(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut sorted = Vec::with_capacity(v6.len() + v4.len());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => return sorted,
+            (a, b) => sorted.extend(a.into_iter().chain(b)),
+        }
     }
 }
 
@@ -257,6 +778,9 @@ impl UdpTrackerSession {
 struct ConnectionInfo {
     id: u64,
     expiration: Instant,
+    /// Whether the connection ID was obtained from the connection cache
+    /// rather than from a handshake made in this session
+    cached: bool,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq)]
@@ -279,8 +803,8 @@ impl<T> Response<T> {
     {
         let mut view = TryBytes::from(buf.slice(0..));
         if view.try_get::<u32>() == Ok(ERROR_ACTION) {
+            // The transaction ID has already been checked by `recv_reply()`.
             let _transaction_id = view.try_get::<u32>()?;
-            // TODO: Should we bother to check the transaction ID?
             let message = view.into_string_lossy();
             Ok(Response::Failure(message))
         } else {
@@ -338,17 +862,21 @@ struct UdpScrapeRequest<'a> {
     connection_id: u64,
     transaction_id: u32,
     info_hashes: &'a [InfoHash],
+    /// Encoded BEP 41 options
+    extensions: &'a [u8],
 }
 
 impl From<UdpScrapeRequest<'_>> for Bytes {
     fn from(req: UdpScrapeRequest<'_>) -> Bytes {
-        let mut buf = BytesMut::with_capacity(16 + 20 * req.info_hashes.len());
+        let mut buf =
+            BytesMut::with_capacity(16 + 20 * req.info_hashes.len() + req.extensions.len());
         buf.put_u64(req.connection_id);
         buf.put_u32(SCRAPE_ACTION);
         buf.put_u32(req.transaction_id);
         for ih in req.info_hashes {
-            buf.put(ih.as_bytes());
+            buf.put(ih.tracker_bytes());
         }
+        buf.put(req.extensions);
         buf.freeze()
     }
 }
@@ -383,11 +911,142 @@ impl TryFrom<Bytes> for UdpScrapeResponse {
     }
 }
 
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct UdpAnnounceRequest<'a> {
+    connection_id: u64,
+    transaction_id: u32,
+    info_hash: InfoHash,
+    params: &'a AnnounceParams,
+    /// Encoded BEP 41 options
+    extensions: &'a [u8],
+}
+
+impl From<UdpAnnounceRequest<'_>> for Bytes {
+    fn from(req: UdpAnnounceRequest<'_>) -> Bytes {
+        let mut buf = BytesMut::with_capacity(98 + req.extensions.len());
+        buf.put_u64(req.connection_id);
+        buf.put_u32(ANNOUNCE_ACTION);
+        buf.put_u32(req.transaction_id);
+        buf.put(req.info_hash.tracker_bytes());
+        buf.put(req.params.peer_id.as_slice());
+        buf.put_u64(req.params.downloaded);
+        buf.put_u64(req.params.left);
+        buf.put_u64(req.params.uploaded);
+        buf.put_u32(match req.params.event {
+            AnnounceEvent::None => 0,
+            AnnounceEvent::Completed => 1,
+            AnnounceEvent::Started => 2,
+            AnnounceEvent::Stopped => 3,
+        });
+        // IP address: 0 = use the sender's address
+        buf.put_u32(0);
+        buf.put_u32(req.params.key);
+        // num_want: -1 = let the tracker decide
+        buf.put_i32(
+            req.params
+                .numwant
+                .map_or(-1, |n| i32::try_from(n).unwrap_or(i32::MAX)),
+        );
+        buf.put_u16(req.params.port);
+        buf.put(req.extensions);
+        buf.freeze()
+    }
+}
+
+/// A UDP tracker protocol extension option (BEP 41)
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum UdpOption<'a> {
+    EndOfOptions,
+    // Only used for padding, which we have no need for outside of tests
+    #[cfg_attr(not(test), expect(dead_code))]
+    Nop,
+    /// A chunk of at most 255 bytes of the tracker URL's path & query.  The
+    /// tracker concatenates all `UrlData` options in a request.
+    UrlData(&'a [u8]),
+}
+
+impl UdpOption<'_> {
+    fn put(self, buf: &mut BytesMut) {
+        match self {
+            UdpOption::EndOfOptions => buf.put_u8(0),
+            UdpOption::Nop => buf.put_u8(1),
+            UdpOption::UrlData(data) => {
+                buf.put_u8(2);
+                buf.put_u8(u8::try_from(data.len()).expect("URLData chunk should fit in a u8"));
+                buf.put(data);
+            }
+        }
+    }
+}
+
+/// Encode the path & query string of a tracker URL as a sequence of `UrlData`
+/// options terminated by `EndOfOptions`.  If `urldata` is empty, no options
+/// are produced.
+fn encode_url_data(urldata: &str) -> Bytes {
+    let mut buf = BytesMut::new();
+    if !urldata.is_empty() {
+        for chunk in urldata.as_bytes().chunks(usize::from(u8::MAX)) {
+            UdpOption::UrlData(chunk).put(&mut buf);
+        }
+        UdpOption::EndOfOptions.put(&mut buf);
+    }
+    buf.freeze()
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct UdpAnnounceResponse {
+    transaction_id: u32,
+    announcement: Announcement,
+}
+
+impl UdpAnnounceResponse {
+    /// Parse an announce response.  `ipv6` indicates whether the peers in
+    /// the response are IPv6 addresses, which is the case when the request
+    /// was sent over IPv6.
+    fn from_bytes(buf: Bytes, ipv6: bool) -> Result<Self, UdpTrackerError> {
+        let mut buf = TryBytes::from(buf);
+        let action = buf.try_get::<u32>()?;
+        if action != ANNOUNCE_ACTION {
+            return Err(UdpTrackerError::BadAction {
+                expected: ANNOUNCE_ACTION,
+                got: action,
+            });
+        }
+        let transaction_id = buf.try_get::<u32>()?;
+        let interval = buf.try_get::<u32>()?;
+        let leechers = buf.try_get::<u32>()?;
+        let seeders = buf.try_get::<u32>()?;
+        let peers = if ipv6 {
+            buf.try_get_all::<SocketAddrV6>()?
+                .into_iter()
+                .map(SocketAddr::V6)
+                .collect()
+        } else {
+            buf.try_get_all::<SocketAddrV4>()?
+                .into_iter()
+                .map(SocketAddr::V4)
+                .collect()
+        };
+        Ok(UdpAnnounceResponse {
+            transaction_id,
+            announcement: Announcement {
+                interval,
+                min_interval: None,
+                seeders: Some(seeders),
+                leechers: Some(leechers),
+                tracker_id: None,
+                warning: None,
+                peers,
+            },
+        })
+    }
+}
+
 #[derive(Debug, Error)]
 pub(crate) enum UdpTrackerError {
     #[error("failed to resolve remote hostname")]
     Lookup(#[source] std::io::Error),
-    #[error("remote hostname did not resolve to any IP addresses")]
+    #[error("remote hostname did not resolve to any usable IP addresses")]
     NoResolve,
     #[error("failed to bind UDP socket")]
     Bind(#[source] std::io::Error),
@@ -397,22 +1056,40 @@ pub(crate) enum UdpTrackerError {
     Send(#[source] std::io::Error),
     #[error("failed to receive UDP packet")]
     Recv(#[source] std::io::Error),
+    #[error("failed to relay UDP packets through SOCKS proxy")]
+    Socks(#[from] SocksError),
+    #[error("UDP tracker did not reply to request after {0} retransmissions")]
+    NoReply(u32),
     #[error("UDP tracker sent response with invalid length")]
     PacketLen(#[from] PacketError),
     #[error(
         "UDP tracker sent response with unexpected or unsupported action; expected {expected}, got {got}"
     )]
     BadAction { expected: u32, got: u32 },
-    #[error(
-        "response from UDP tracker did not contain expected transaction ID; expected {expected:#x}, got {got:#x}"
-    )]
-    XactionMismatch { expected: u32, got: u32 },
     #[error(
         "scrape response from UDP tracker did not contain expected number of statistics; expected {expected}, got {got}"
     )]
     ScrapeLenMismatch { expected: usize, got: usize },
 }
 
+impl UdpTrackerError {
+    pub(super) fn kind(&self) -> &'static str {
+        match self {
+            UdpTrackerError::Lookup(_)
+            | UdpTrackerError::NoResolve
+            | UdpTrackerError::Bind(_)
+            | UdpTrackerError::Connect(_)
+            | UdpTrackerError::Send(_)
+            | UdpTrackerError::Recv(_)
+            | UdpTrackerError::Socks(_) => "network",
+            UdpTrackerError::NoReply(_) => "timeout",
+            UdpTrackerError::PacketLen(_)
+            | UdpTrackerError::BadAction { .. }
+            | UdpTrackerError::ScrapeLenMismatch { .. } => "protocol",
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -517,6 +1194,7 @@ mod tests {
             connection_id: 0xFAEAA63F0C55F0BC,
             transaction_id: 0x7F541CC1,
             info_hashes: info_hashes.as_slice(),
+            extensions: &[],
         };
         let buf = Bytes::from(req);
         assert_eq!(buf, b"\xFA\xEA\xA6\x3F\x0C\x55\xF0\xBC\0\0\0\x02\x7F\x54\x1C\xC1\x28\xc5\x51\x96\xf5\x77\x53\xc4\x0a\xce\xb6\xfb\x58\x61\x7e\x69\x95\xa7\xed\xdb\xda\x39\xa3\xee\x5e\x6b\x4b\x0d\x32\x55\xbf\xef\x95\x60\x18\x90\xaf\xd8\x07\x09".as_slice());
@@ -532,17 +1210,541 @@ mod tests {
                 transaction_id: 0x7F541CC1,
                 scrapes: vec![
                     Scrape {
-                        complete: 10,
-                        incomplete: 0,
-                        downloaded: 32
+                        complete: Some(10),
+                        incomplete: Some(0),
+                        downloaded: Some(32),
+                        ..Scrape::default()
                     },
                     Scrape {
-                        complete: 105,
-                        incomplete: 42,
-                        downloaded: 1337
+                        complete: Some(105),
+                        incomplete: Some(42),
+                        downloaded: Some(1337),
+                        ..Scrape::default()
                     },
                 ],
             }
         );
     }
+
+    #[test]
+    fn build_announce_request() {
+        let params = AnnounceParams {
+            peer_id: *b"-TS0000-abcdefghijkl",
+            key: 0xDEADBEEF,
+            port: 6881,
+            uploaded: 0x10,
+            downloaded: 0x20,
+            left: 0x30,
+            event: AnnounceEvent::Started,
+            numwant: None,
+        };
+        let req = UdpAnnounceRequest {
+            connection_id: 0xFAEAA63F0C55F0BC,
+            transaction_id: 0x7F541CC1,
+            info_hash: "28c55196f57753c40aceb6fb58617e6995a7eddb"
+                .parse::<InfoHash>()
+                .unwrap(),
+            params: &params,
+            extensions: &[],
+        };
+        let buf = Bytes::from(req);
+        assert_eq!(buf.len(), 98);
+        assert_eq!(buf, b"\xFA\xEA\xA6\x3F\x0C\x55\xF0\xBC\0\0\0\x01\x7F\x54\x1C\xC1\x28\xc5\x51\x96\xf5\x77\x53\xc4\x0a\xce\xb6\xfb\x58\x61\x7e\x69\x95\xa7\xed\xdb-TS0000-abcdefghijkl\0\0\0\0\0\0\0\x20\0\0\0\0\0\0\0\x30\0\0\0\0\0\0\0\x10\0\0\0\x02\0\0\0\0\xDE\xAD\xBE\xEF\xFF\xFF\xFF\xFF\x1A\xE1".as_slice());
+    }
+
+    #[test]
+    fn parse_announce_response_ipv4() {
+        let buf = Bytes::from(b"\0\0\0\x01\x7F\x54\x1C\xC1\0\0\x07\x08\0\0\0\x2A\0\0\0\x69\xC0\xA8\x01\x02\x1A\xE1\x0A\0\0\x01\xC8\xD5".as_slice());
+        let res = UdpAnnounceResponse::from_bytes(buf, false).unwrap();
+        assert_eq!(
+            res,
+            UdpAnnounceResponse {
+                transaction_id: 0x7F541CC1,
+                announcement: Announcement {
+                    interval: 1800,
+                    min_interval: None,
+                    seeders: Some(105),
+                    leechers: Some(42),
+                    tracker_id: None,
+                    warning: None,
+                    peers: vec![
+                        "192.168.1.2:6881".parse::<SocketAddr>().unwrap(),
+                        "10.0.0.1:51413".parse::<SocketAddr>().unwrap(),
+                    ],
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn parse_announce_response_ipv6() {
+        let buf = Bytes::from(b"\0\0\0\x01\x7F\x54\x1C\xC1\0\0\x07\x08\0\0\0\0\0\0\0\x01\x3F\xFF\0\0\0\0\0\0\0\0\0\0\0\0\xAB\xCD\x1A\xE1".as_slice());
+        let res = UdpAnnounceResponse::from_bytes(buf, true).unwrap();
+        assert_eq!(
+            res.announcement.peers,
+            ["[3fff::abcd]:6881".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn parse_announce_response_bad_peers() {
+        let buf = Bytes::from(
+            b"\0\0\0\x01\x7F\x54\x1C\xC1\0\0\x07\x08\0\0\0\0\0\0\0\x01\xC0\xA8\x01".as_slice(),
+        );
+        assert!(matches!(
+            UdpAnnounceResponse::from_bytes(buf, false),
+            Err(UdpTrackerError::PacketLen(PacketError::Short))
+        ));
+    }
+
+    #[test]
+    fn encode_short_url_data() {
+        assert_eq!(
+            encode_url_data("/announce?passkey=abc"),
+            b"\x02\x15/announce?passkey=abc\x00".as_slice()
+        );
+    }
+
+    #[test]
+    fn encode_long_url_data() {
+        let urldata = format!("/announce?passkey={}", "x".repeat(300));
+        let mut expected = BytesMut::new();
+        expected.put(b"\x02\xFF".as_slice());
+        expected.put(&urldata.as_bytes()[..255]);
+        expected.put(b"\x02\x3F".as_slice());
+        expected.put(&urldata.as_bytes()[255..]);
+        expected.put_u8(0);
+        assert_eq!(encode_url_data(&urldata), expected);
+    }
+
+    #[test]
+    fn encode_empty_url_data() {
+        assert!(encode_url_data("").is_empty());
+    }
+
+    #[test]
+    fn put_nop_option() {
+        let mut buf = BytesMut::new();
+        UdpOption::Nop.put(&mut buf);
+        UdpOption::UrlData(b"/a").put(&mut buf);
+        UdpOption::EndOfOptions.put(&mut buf);
+        assert_eq!(buf, b"\x01\x02\x02/a\x00".as_slice());
+    }
+
+    #[test]
+    fn build_scrape_request_with_url_data() {
+        let info_hashes = vec![
+            "28c55196f57753c40aceb6fb58617e6995a7eddb"
+                .parse::<InfoHash>()
+                .unwrap(),
+        ];
+        let extensions = encode_url_data("/ann");
+        let req = UdpScrapeRequest {
+            connection_id: 0xFAEAA63F0C55F0BC,
+            transaction_id: 0x7F541CC1,
+            info_hashes: info_hashes.as_slice(),
+            extensions: &extensions,
+        };
+        let buf = Bytes::from(req);
+        assert_eq!(buf, b"\xFA\xEA\xA6\x3F\x0C\x55\xF0\xBC\0\0\0\x02\x7F\x54\x1C\xC1\x28\xc5\x51\x96\xf5\x77\x53\xc4\x0a\xce\xb6\xfb\x58\x61\x7e\x69\x95\xa7\xed\xdb\x02\x04/ann\x00".as_slice());
+    }
+
+    #[test]
+    fn sort_mixed_addresses() {
+        let addrs = [
+            "192.0.2.1:6969",
+            "192.0.2.2:6969",
+            "192.0.2.3:6969",
+            "[2001:db8::1]:6969",
+            "[2001:db8::2]:6969",
+        ]
+        .map(|s| s.parse::<SocketAddr>().unwrap());
+        assert_eq!(
+            sort_addresses(addrs.to_vec()),
+            [
+                "[2001:db8::1]:6969",
+                "192.0.2.1:6969",
+                "[2001:db8::2]:6969",
+                "192.0.2.2:6969",
+                "192.0.2.3:6969",
+            ]
+            .map(|s| s.parse::<SocketAddr>().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn connect_past_unresponsive_address() {
+        // A bound socket that never replies stands in for an unreachable
+        // address.
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let tracker = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addrs = vec![silent.local_addr().unwrap(), tracker.local_addr().unwrap()];
+        let server = async {
+            let mut buf = [0; 16];
+            let (len, peer) = tracker.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 16);
+            let mut resp = BytesMut::with_capacity(16);
+            resp.put_u32(CONNECT_ACTION);
+            resp.put_slice(&buf[12..16]);
+            resp.put_u64(0x0123456789ABCDEF);
+            tracker.send_to(&resp, peer).await.unwrap();
+        };
+        let options = UdpOptions::default();
+        let (session, ()) = tokio::join!(
+            UdpTrackerSession::connect_any(addrs, Bytes::new(), &options),
+            server
+        );
+        let session = session.unwrap();
+        assert_eq!(session.peer, Some(tracker.local_addr().unwrap()));
+        assert_eq!(session.conn.map(|c| c.id), Some(0x0123456789ABCDEF));
+    }
+
+    #[test]
+    fn retry_waits() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.wait(0), Duration::from_secs(15));
+        assert_eq!(policy.wait(1), Duration::from_secs(30));
+        assert_eq!(policy.wait(8), Duration::from_secs(3840));
+        assert_eq!(policy.wait(40), Duration::from_secs(15) * u32::MAX);
+    }
+
+    #[tokio::test]
+    async fn give_up_after_max_retries() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let options = UdpOptions {
+            retry: RetryPolicy {
+                initial_timeout: Duration::from_millis(10),
+                backoff: NonZeroU32::new(2).unwrap(),
+                max_retries: 2,
+            },
+            ..UdpOptions::default()
+        };
+        let r = UdpTrackerSession::connect_any(
+            vec![silent.local_addr().unwrap()],
+            Bytes::new(),
+            &options,
+        )
+        .await;
+        let Err(TrackerError::Udp(UdpTrackerError::NoReply(2))) = r else {
+            panic!("connecting did not fail with NoReply(2): {r:?}");
+        };
+        let mut buf = [0; 16];
+        for _ in 0..3 {
+            silent.recv_from(&mut buf).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn give_up_when_only_connects_are_answered() {
+        let tracker = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = tracker.local_addr().unwrap();
+        let options = UdpOptions {
+            timeout: Duration::from_secs(3600),
+            retry: RetryPolicy {
+                initial_timeout: Duration::from_millis(20),
+                backoff: NonZeroU32::new(2).unwrap(),
+                max_retries: 3,
+            },
+            connection_lifetime: Duration::from_millis(50),
+            ..UdpOptions::default()
+        };
+        let info_hash = "28c55196f57753c40aceb6fb58617e6995a7eddb"
+            .parse::<InfoHash>()
+            .unwrap();
+        let server = async {
+            let mut buf = [0; 64];
+            loop {
+                let (len, peer) = tracker.recv_from(&mut buf).await.unwrap();
+                if len == 16 {
+                    let mut resp = BytesMut::new();
+                    resp.put_u32(CONNECT_ACTION);
+                    resp.put_slice(&buf[12..16]);
+                    resp.put_u64(0x0123456789ABCDEF);
+                    tracker.send_to(&resp, peer).await.unwrap();
+                }
+            }
+        };
+        let client = async {
+            let mut session = UdpTrackerSession::connect_any(vec![addr], Bytes::new(), &options)
+                .await
+                .unwrap();
+            session.scrape(&[info_hash]).await
+        };
+        let r = tokio::select! {
+            r = timeout(Duration::from_secs(10), client) => r.expect("scrape should give up"),
+            () = server => unreachable!(),
+        };
+        let Err(TrackerError::Udp(UdpTrackerError::NoReply(3))) = r else {
+            panic!("scraping did not fail with NoReply(3): {r:?}");
+        };
+    }
+
+    #[tokio::test]
+    async fn ignore_stray_packets() {
+        let tracker = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addrs = vec![tracker.local_addr().unwrap()];
+        let server = async {
+            let mut buf = [0; 16];
+            let (len, peer) = tracker.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 16);
+            let xid = u32::from_be_bytes(buf[12..16].try_into().unwrap());
+            // A late reply to some earlier transaction:
+            let mut stray = BytesMut::new();
+            stray.put_u32(CONNECT_ACTION);
+            stray.put_u32(xid.wrapping_add(1));
+            stray.put_u64(0xDEADBEEFDEADBEEF);
+            tracker.send_to(&stray, peer).await.unwrap();
+            // An error for some other transaction:
+            let mut stray = BytesMut::new();
+            stray.put_u32(ERROR_ACTION);
+            stray.put_u32(xid.wrapping_add(2));
+            stray.put_slice(b"Go away");
+            tracker.send_to(&stray, peer).await.unwrap();
+            // Garbage:
+            tracker.send_to(b"\x00\x00", peer).await.unwrap();
+            let mut resp = BytesMut::new();
+            resp.put_u32(CONNECT_ACTION);
+            resp.put_u32(xid);
+            resp.put_u64(0x0123456789ABCDEF);
+            tracker.send_to(&resp, peer).await.unwrap();
+        };
+        let options = UdpOptions::default();
+        let (session, ()) = tokio::join!(
+            UdpTrackerSession::connect_any(addrs, Bytes::new(), &options),
+            server
+        );
+        assert_eq!(
+            session.unwrap().conn.map(|c| c.id),
+            Some(0x0123456789ABCDEF)
+        );
+    }
+
+    #[test]
+    fn get_reply_transaction_id() {
+        assert_eq!(
+            reply_transaction_id(b"\0\0\0\x03\x7F\x54\x1C\xC1Go away"),
+            Some(0x7F541CC1)
+        );
+        assert_eq!(reply_transaction_id(b"\0\0\0\x03\x7F\x54\x1C"), None);
+    }
+
+    #[tokio::test]
+    async fn replace_rejected_cached_connection() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let cache = ConnectionCache::load(tmpdir.path().join("connections.json")).unwrap();
+        let tracker = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = tracker.local_addr().unwrap();
+        cache.insert(addr, 0xBAD, Instant::now() + Duration::from_secs(60));
+        let options = UdpOptions {
+            connection_cache: Some(Arc::new(cache)),
+            ..UdpOptions::default()
+        };
+        let info_hash = "28c55196f57753c40aceb6fb58617e6995a7eddb"
+            .parse::<InfoHash>()
+            .unwrap();
+        let server = async {
+            let mut buf = [0; 64];
+            // Scrape with the cached connection ID, which is rejected:
+            let (len, peer) = tracker.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 36);
+            assert_eq!(buf[..8], 0xBADu64.to_be_bytes());
+            let mut resp = BytesMut::new();
+            resp.put_u32(ERROR_ACTION);
+            resp.put_slice(&buf[12..16]);
+            resp.put_slice(b"Connection ID missmatch.");
+            tracker.send_to(&resp, peer).await.unwrap();
+            // Fresh connection handshake:
+            let (len, peer) = tracker.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 16);
+            let mut resp = BytesMut::new();
+            resp.put_u32(CONNECT_ACTION);
+            resp.put_slice(&buf[12..16]);
+            resp.put_u64(0x600D);
+            tracker.send_to(&resp, peer).await.unwrap();
+            // Scrape with the new connection ID:
+            let (len, peer) = tracker.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 36);
+            assert_eq!(buf[..8], 0x600Du64.to_be_bytes());
+            let mut resp = BytesMut::new();
+            resp.put_u32(SCRAPE_ACTION);
+            resp.put_slice(&buf[12..16]);
+            resp.put_slice(b"\0\0\0\x0A\0\0\0\x20\0\0\0\x05");
+            tracker.send_to(&resp, peer).await.unwrap();
+        };
+        let client = async {
+            let mut session = UdpTrackerSession::connect_any(vec![addr], Bytes::new(), &options)
+                .await
+                .unwrap();
+            session.scrape(&[info_hash]).await
+        };
+        let (r, ()) = tokio::join!(client, server);
+        assert_eq!(
+            r.unwrap(),
+            ScrapeMap::from([(
+                info_hash,
+                Scrape {
+                    complete: Some(10),
+                    incomplete: Some(5),
+                    downloaded: Some(32),
+                    ..Scrape::default()
+                }
+            )])
+        );
+        let cache = options.connection_cache.unwrap();
+        assert_eq!(cache.get(addr).map(|(id, _)| id), Some(0x600D));
+    }
+
+    #[tokio::test]
+    async fn fall_back_from_dead_cached_address() {
+        let tmpdir = tempfile::tempdir().unwrap();
+        let cache = ConnectionCache::load(tmpdir.path().join("connections.json")).unwrap();
+        // A bound socket that never replies stands in for an address that
+        // has gone dead since its connection ID was cached.
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let tracker = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let dead = silent.local_addr().unwrap();
+        let live = tracker.local_addr().unwrap();
+        cache.insert(dead, 0xDEAD, Instant::now() + Duration::from_secs(60));
+        let options = UdpOptions {
+            retry: RetryPolicy {
+                initial_timeout: Duration::from_millis(100),
+                ..RetryPolicy::default()
+            },
+            connection_cache: Some(Arc::new(cache)),
+            ..UdpOptions::default()
+        };
+        let info_hash = "28c55196f57753c40aceb6fb58617e6995a7eddb"
+            .parse::<InfoHash>()
+            .unwrap();
+        let server = async {
+            let mut buf = [0; 64];
+            let (len, peer) = tracker.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 16);
+            let mut resp = BytesMut::new();
+            resp.put_u32(CONNECT_ACTION);
+            resp.put_slice(&buf[12..16]);
+            resp.put_u64(0x600D);
+            tracker.send_to(&resp, peer).await.unwrap();
+            let (len, peer) = tracker.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, 36);
+            assert_eq!(buf[..8], 0x600Du64.to_be_bytes());
+            let mut resp = BytesMut::new();
+            resp.put_u32(SCRAPE_ACTION);
+            resp.put_slice(&buf[12..16]);
+            resp.put_slice(b"\0\0\0\x0A\0\0\0\x20\0\0\0\x05");
+            tracker.send_to(&resp, peer).await.unwrap();
+        };
+        let client = async {
+            let mut session =
+                UdpTrackerSession::connect_any(vec![live, dead], Bytes::new(), &options)
+                    .await
+                    .unwrap();
+            // Nothing has been sent yet, so the cached address is only
+            // presumed to work.
+            assert_eq!(session.peer, Some(dead));
+            let r = session.scrape(&[info_hash]).await;
+            (session, r)
+        };
+        let ((session, r), ()) = tokio::join!(client, server);
+        assert_eq!(
+            r.unwrap(),
+            ScrapeMap::from([(
+                info_hash,
+                Scrape {
+                    complete: Some(10),
+                    incomplete: Some(5),
+                    downloaded: Some(32),
+                    ..Scrape::default()
+                }
+            )])
+        );
+        assert_eq!(session.peer, Some(live));
+        let cache = options.connection_cache.unwrap();
+        assert_eq!(cache.get(dead), None);
+        assert_eq!(cache.get(live).map(|(id, _)| id), Some(0x600D));
+        let mut buf = [0; 64];
+        let (len, _) = silent.recv_from(&mut buf).await.unwrap();
+        assert_eq!(len, 36);
+        assert_eq!(buf[..8], 0xDEADu64.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn scrape_through_socks_proxy() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let options = UdpOptions {
+            proxy: Some(
+                format!("socks5h://{}", listener.local_addr().unwrap())
+                    .parse()
+                    .unwrap(),
+            ),
+            ..UdpOptions::default()
+        };
+        let info_hash = "28c55196f57753c40aceb6fb58617e6995a7eddb"
+            .parse::<InfoHash>()
+            .unwrap();
+        let tracker_addr = "192.0.2.1:6969".parse::<SocketAddr>().unwrap();
+        let server = async {
+            let (mut control, _) = listener.accept().await.unwrap();
+            let mut greeting = [0; 3];
+            control.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            control.write_all(&[5, 0]).await.unwrap();
+            let mut req = [0; 10];
+            control.read_exact(&mut req).await.unwrap();
+            assert_eq!(req, [5, 3, 0, 1, 0, 0, 0, 0, 0, 0]);
+            let relay = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let mut reply = BytesMut::from(&[5, 0, 0, 1, 127, 0, 0, 1][..]);
+            reply.put_u16(relay.local_addr().unwrap().port());
+            control.write_all(&reply).await.unwrap();
+            let header = b"\0\0\0\x03\x0Ftracker.invalid\x1B\x39";
+            let mut buf = [0; 128];
+            // Connection handshake:
+            let (len, client) = relay.recv_from(&mut buf).await.unwrap();
+            assert_eq!(len, header.len() + 16);
+            assert_eq!(&buf[..header.len()], header);
+            let req = &buf[header.len()..len];
+            let mut resp = BytesMut::from(&b"\0\0\0\x01\xC0\x00\x02\x01\x1B\x39"[..]);
+            resp.put_u32(CONNECT_ACTION);
+            resp.put_slice(&req[12..16]);
+            resp.put_u64(0x0123456789ABCDEF);
+            relay.send_to(&resp, client).await.unwrap();
+            // Scrape:
+            let (len, client) = relay.recv_from(&mut buf).await.unwrap();
+            let req = &buf[header.len()..len];
+            assert_eq!(req.len(), 36);
+            assert_eq!(req[..8], 0x0123456789ABCDEFu64.to_be_bytes());
+            let mut resp = BytesMut::from(&b"\0\0\0\x01\xC0\x00\x02\x01\x1B\x39"[..]);
+            resp.put_u32(SCRAPE_ACTION);
+            resp.put_slice(&req[12..16]);
+            resp.put_slice(b"\0\0\0\x0A\0\0\0\x20\0\0\0\x05");
+            relay.send_to(&resp, client).await.unwrap();
+            control
+        };
+        let client = async {
+            let mut session =
+                UdpTrackerSession::new("tracker.invalid", 6969, Bytes::new(), &options)
+                    .await
+                    .unwrap();
+            let r = session.scrape(&[info_hash]).await;
+            (session, r)
+        };
+        let ((session, r), _control) = tokio::join!(client, server);
+        assert_eq!(
+            r.unwrap(),
+            ScrapeMap::from([(
+                info_hash,
+                Scrape {
+                    complete: Some(10),
+                    incomplete: Some(5),
+                    downloaded: Some(32),
+                    ..Scrape::default()
+                }
+            )])
+        );
+        assert_eq!(session.peer, Some(tracker_addr));
+        assert!(!session.ipv6);
+    }
 }
@@ -0,0 +1,471 @@
+use super::{
+    AnnounceParams, AnnounceResult, Announcement, Scrape, ScrapeMap, ScrapeReport, TrackerError,
+    TrackerUrlError, with_timeout,
+};
+use crate::infohash::InfoHash;
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+use url::Url;
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// A `WebTorrent` tracker, which speaks a JSON variant of the HTTP tracker
+/// protocol over a WebSocket
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct WsTracker(Url);
+
+/// Settings for communicating with WebSocket trackers
+#[derive(Clone, Debug)]
+pub(crate) struct WsOptions {
+    /// Maximum number of info hashes to send in a single scrape request
+    pub(crate) batch_size: NonZeroUsize,
+    /// Maximum amount of time to wait for each request (including opening
+    /// the WebSocket, if needed) to complete
+    pub(crate) timeout: Duration,
+}
+
+impl Default for WsOptions {
+    fn default() -> WsOptions {
+        WsOptions {
+            batch_size: NonZeroUsize::new(50).expect("50 should be nonzero"),
+            timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl WsTracker {
+    #[tracing::instrument(name = "scrape-ws", skip_all, fields(tracker = %self.0))]
+    pub(crate) async fn scrape(&self, hashes: &[InfoHash], options: &WsOptions) -> ScrapeReport {
+        let mut report = ScrapeReport::default();
+        // All batches are sent over the same WebSocket.
+        let mut stream = match with_timeout(options.timeout, self.connect()).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                report.fail(hashes, e);
+                return report;
+            }
+        };
+        for batch in hashes.chunks(options.batch_size.get()) {
+            let r = with_timeout(options.timeout, scrape_batch(&mut stream, batch)).await;
+            report.record(batch, r);
+        }
+        close(stream).await;
+        report
+    }
+
+    /// Announce each of the given info hashes to the tracker in turn and
+    /// return the results in the same order.  No WebRTC offers are sent, so
+    /// the returned announcements never contain any peers.
+    #[tracing::instrument(name = "announce-ws", skip_all, fields(tracker = %self.0))]
+    pub(crate) async fn announce(
+        &self,
+        hashes: &[InfoHash],
+        params: &AnnounceParams,
+        options: &WsOptions,
+    ) -> Vec<AnnounceResult> {
+        let mut stream = match with_timeout(options.timeout, self.connect()).await {
+            Ok(stream) => stream,
+            Err(e) => {
+                let e = Arc::new(e);
+                return hashes.iter().map(|_| Err(Arc::clone(&e))).collect();
+            }
+        };
+        let mut results = Vec::with_capacity(hashes.len());
+        for &ih in hashes {
+            let r = with_timeout(options.timeout, announce_one(&mut stream, ih, params)).await;
+            results.push(r.map_err(Arc::new));
+        }
+        close(stream).await;
+        results
+    }
+
+    async fn connect(&self) -> Result<WsStream, TrackerError> {
+        tracing::info!("Opening WebSocket to tracker");
+        // The handshake future is large, so keep it off the stack.
+        let (stream, _) = Box::pin(connect_async(self.0.as_str()))
+            .await
+            .map_err(|e| WsTrackerError::Connect(Box::new(e)))?;
+        Ok(stream)
+    }
+}
+
+async fn scrape_batch(
+    stream: &mut WsStream,
+    hashes: &[InfoHash],
+) -> Result<ScrapeMap, TrackerError> {
+    tracing::info!("Sending scrape request to tracker");
+    let req = WsRequest {
+        action: "scrape",
+        info_hash: hashes
+            .iter()
+            .map(|ih| binary_string(ih.tracker_bytes()))
+            .collect(),
+        ..WsRequest::default()
+    };
+    let resp = exchange(stream, &req, |resp| {
+        resp.failure_reason.is_some() || resp.files.is_some()
+    })
+    .await?;
+    if let Some(msg) = resp.failure_reason {
+        return Err(TrackerError::Failure(msg));
+    }
+    tracing::info!("Received scrape response from tracker");
+    let mut scrapes = ScrapeMap::new();
+    for (key, stats) in resp.files.unwrap_or_default() {
+        let info_hash = binary_string_bytes(&key)
+            .and_then(|bs| InfoHash::try_from(bs).ok())
+            .ok_or(WsTrackerError::BadInfoHash)?;
+        scrapes.insert(
+            info_hash,
+            Scrape {
+                complete: Some(stats.complete.0),
+                incomplete: Some(stats.incomplete.0),
+                downloaded: stats.downloaded.map(|c| c.0),
+                ..Scrape::default()
+            },
+        );
+    }
+    Ok(scrapes)
+}
+
+async fn announce_one(
+    stream: &mut WsStream,
+    info_hash: InfoHash,
+    params: &AnnounceParams,
+) -> Result<Announcement, TrackerError> {
+    tracing::info!("Sending announce request to tracker");
+    let ih = binary_string(info_hash.tracker_bytes());
+    let req = WsRequest {
+        action: "announce",
+        info_hash: vec![ih.clone()],
+        peer_id: Some(binary_string(&params.peer_id)),
+        uploaded: Some(params.uploaded),
+        downloaded: Some(params.downloaded),
+        left: Some(params.left),
+        event: params.event.http_value(),
+        numwant: params.numwant,
+        offers: Some(Vec::new()),
+    };
+    // Other clients' WebRTC offers are relayed as "announce" messages too,
+    // so only a message with an "interval" is the response.
+    let resp = exchange(stream, &req, |resp| {
+        resp.info_hash.as_ref().is_none_or(|h| *h == ih)
+            && (resp.failure_reason.is_some() || resp.interval.is_some())
+    })
+    .await?;
+    if let Some(msg) = resp.failure_reason {
+        return Err(TrackerError::Failure(msg));
+    }
+    tracing::info!("Received announce response from tracker");
+    Ok(Announcement {
+        interval: resp.interval.ok_or(WsTrackerError::NoInterval)?.0,
+        min_interval: resp.min_interval.map(|c| c.0),
+        seeders: resp.complete.map(|c| c.0),
+        leechers: resp.incomplete.map(|c| c.0),
+        tracker_id: None,
+        warning: resp.warning_message,
+        peers: Vec::new(),
+    })
+}
+
+/// Send a request over the WebSocket and return the first message received
+/// in reply with the same action for which `is_reply` returns true.  Other
+/// messages, including any that cannot be parsed, are logged & discarded.
+async fn exchange<F>(
+    stream: &mut WsStream,
+    req: &WsRequest<'_>,
+    is_reply: F,
+) -> Result<WsResponse, WsTrackerError>
+where
+    F: Fn(&WsResponse) -> bool,
+{
+    let body = serde_json::to_string(req).expect("request should serialize");
+    stream
+        .send(Message::text(body))
+        .await
+        .map_err(|e| WsTrackerError::Send(Box::new(e)))?;
+    loop {
+        let msg = match stream.next().await {
+            Some(Ok(msg)) => msg,
+            Some(Err(e)) => return Err(WsTrackerError::Recv(Box::new(e))),
+            None => return Err(WsTrackerError::Closed),
+        };
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Close(_) => return Err(WsTrackerError::Closed),
+            _ => continue,
+        };
+        let resp = match serde_json::from_str::<WsResponse>(&text) {
+            Ok(resp) => resp,
+            Err(e) => {
+                tracing::info!("Ignoring unparseable message from tracker: {e}");
+                continue;
+            }
+        };
+        if resp.action.as_deref().is_none_or(|a| a == req.action) && is_reply(&resp) {
+            return Ok(resp);
+        }
+        tracing::info!("Ignoring unexpected message from tracker");
+    }
+}
+
+/// Close the WebSocket, ignoring any errors, as all requests are done
+async fn close(mut stream: WsStream) {
+    if let Err(e) = stream.close(None).await {
+        tracing::info!("Failed to close WebSocket cleanly: {e}");
+    }
+}
+
+/// Encode bytes as a "binary string", i.e., a string in which each character
+/// has the code point of the corresponding byte, as `WebTorrent` trackers
+/// expect for info hashes & peer IDs
+fn binary_string(bs: &[u8]) -> String {
+    bs.iter().copied().map(char::from).collect()
+}
+
+/// Decode a "binary string" back to bytes.  Returns `None` if the string
+/// contains any characters above U+00FF.
+fn binary_string_bytes(s: &str) -> Option<Vec<u8>> {
+    s.chars().map(|c| u8::try_from(c).ok()).collect()
+}
+
+impl fmt::Display for WsTracker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl TryFrom<Url> for WsTracker {
+    type Error = TrackerUrlError;
+
+    fn try_from(url: Url) -> Result<WsTracker, TrackerUrlError> {
+        let sch = url.scheme();
+        if sch != "ws" && sch != "wss" {
+            return Err(TrackerUrlError::UnsupportedScheme(sch.into()));
+        }
+        if url.host().is_none() {
+            return Err(TrackerUrlError::NoHost);
+        }
+        Ok(WsTracker(url))
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+struct WsRequest<'a> {
+    action: &'a str,
+    info_hash: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peer_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    uploaded: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    downloaded: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    left: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    numwant: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    offers: Option<Vec<()>>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+struct WsResponse {
+    action: Option<String>,
+    #[serde(rename = "failure reason")]
+    failure_reason: Option<String>,
+    #[serde(rename = "warning message")]
+    warning_message: Option<String>,
+    info_hash: Option<String>,
+    files: Option<HashMap<String, WsFileStats>>,
+    interval: Option<Count>,
+    #[serde(rename = "min interval")]
+    min_interval: Option<Count>,
+    complete: Option<Count>,
+    incomplete: Option<Count>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+struct WsFileStats {
+    complete: Count,
+    incomplete: Count,
+    downloaded: Option<Count>,
+}
+
+/// A count or interval in a message from a tracker.  Numbers that are
+/// negative, fractional, or too large for a `u32` are clamped rather than
+/// rejected, as a message that fails to parse is skipped, leaving the request
+/// to wait for a reply until it times out.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Count(u32);
+
+impl<'de> Deserialize<'de> for Count {
+    // Float-to-integer `as` casts saturate, and NaN becomes zero.
+    #[expect(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Count, D::Error> {
+        let n = serde_json::Number::deserialize(deserializer)?;
+        let value = if let Some(u) = n.as_u64() {
+            u32::try_from(u).unwrap_or(u32::MAX)
+        } else if n.is_i64() {
+            0
+        } else {
+            n.as_f64().unwrap_or_default() as u32
+        };
+        Ok(Count(value))
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum WsTrackerError {
+    #[error("failed to open WebSocket to tracker")]
+    Connect(#[source] Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("failed to send message to WebSocket tracker")]
+    Send(#[source] Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("failed to receive message from WebSocket tracker")]
+    Recv(#[source] Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("WebSocket tracker closed the connection before replying")]
+    Closed,
+    #[error("WebSocket tracker response contained invalid info hash")]
+    BadInfoHash,
+    #[error("announce response from WebSocket tracker did not contain interval")]
+    NoInterval,
+}
+
+impl WsTrackerError {
+    pub(super) fn kind(&self) -> &'static str {
+        match self {
+            WsTrackerError::Connect(_)
+            | WsTrackerError::Send(_)
+            | WsTrackerError::Recv(_)
+            | WsTrackerError::Closed => "network",
+            WsTrackerError::BadInfoHash | WsTrackerError::NoInterval => "protocol",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    #[test]
+    fn binary_string_roundtrip() {
+        let bs =
+            b"\x28\xc5\x51\x96\xf5\x77\x53\xc4\x0a\xce\xb6\xfb\x58\x61\x7e\x69\x95\xa7\xed\xdb";
+        let s = binary_string(bs);
+        assert_eq!(s.chars().count(), 20);
+        assert_eq!(binary_string_bytes(&s).as_deref(), Some(bs.as_slice()));
+        assert_eq!(binary_string_bytes("\u{100}"), None);
+    }
+
+    #[test]
+    fn parse_out_of_range_counts() {
+        let resp = serde_json::from_str::<WsResponse>(
+            r#"{"action":"scrape","files":{"x":{"complete":-3,"incomplete":99999999999,"downloaded":7.5}}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            resp.files.unwrap()["x"],
+            WsFileStats {
+                complete: Count(0),
+                incomplete: Count(u32::MAX),
+                downloaded: Some(Count(7)),
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn scrape_ws_tracker() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tracker = WsTracker(
+            format!("ws://{}/", listener.local_addr().unwrap())
+                .parse::<Url>()
+                .unwrap(),
+        );
+        let hashes = [
+            "28c55196f57753c40aceb6fb58617e6995a7eddb",
+            "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+        ]
+        .map(|s| s.parse::<InfoHash>().unwrap());
+        let server = async {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = accept_async(socket).await.unwrap();
+            let Some(Ok(Message::Text(req))) = ws.next().await else {
+                panic!("client did not send text message");
+            };
+            let req = serde_json::from_str::<serde_json::Value>(&req).unwrap();
+            assert_eq!(req["action"], "scrape");
+            let ih0 = binary_string(hashes[0].as_bytes());
+            let ih1 = binary_string(hashes[1].as_bytes());
+            assert_eq!(req["info_hash"], serde_json::json!([ih0, ih1]));
+            ws.send(Message::text("not JSON")).await.unwrap();
+            // A relayed offer for some other swarm:
+            ws.send(Message::text(
+                r#"{"action":"announce","info_hash":"x","offer":{}}"#,
+            ))
+            .await
+            .unwrap();
+            let resp = serde_json::json!({
+                "action": "scrape",
+                "files": {
+                    ih0: {"complete": 10, "incomplete": 0, "downloaded": 32},
+                },
+            });
+            ws.send(Message::text(resp.to_string())).await.unwrap();
+            // Wait for the client to close the connection.
+            while let Some(Ok(_)) = ws.next().await {}
+        };
+        let options = WsOptions::default();
+        let (report, ()) = tokio::join!(tracker.scrape(&hashes, &options), server);
+        assert_eq!(
+            report.get(hashes[0]).unwrap(),
+            Some(Scrape {
+                complete: Some(10),
+                incomplete: Some(0),
+                downloaded: Some(32),
+                ..Scrape::default()
+            })
+        );
+        assert_eq!(report.get(hashes[1]).unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn scrape_ws_tracker_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tracker = WsTracker(
+            format!("ws://{}/", listener.local_addr().unwrap())
+                .parse::<Url>()
+                .unwrap(),
+        );
+        let info_hash = "28c55196f57753c40aceb6fb58617e6995a7eddb"
+            .parse::<InfoHash>()
+            .unwrap();
+        let server = async {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = accept_async(socket).await.unwrap();
+            ws.next().await.unwrap().unwrap();
+            ws.send(Message::text(
+                r#"{"action":"scrape","failure reason":"scrape disabled"}"#,
+            ))
+            .await
+            .unwrap();
+            while let Some(Ok(_)) = ws.next().await {}
+        };
+        let options = WsOptions::default();
+        let hashes = [info_hash];
+        let (report, ()) = tokio::join!(tracker.scrape(&hashes, &options), server);
+        let e = report.get(info_hash).unwrap_err();
+        assert!(matches!(&*e, TrackerError::Failure(msg) if msg == "scrape disabled"));
+    }
+}
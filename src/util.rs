@@ -1,6 +1,11 @@
-use bendy::decoding::{Decoder, FromBencode};
+use bendy::decoding::{Decoder, FromBencode, Object};
 use bytes::{Buf, Bytes};
+use patharg::InputArg;
+use std::borrow::Cow;
+use std::io::{self, BufRead};
+use std::str::FromStr;
 use thiserror::Error;
+use url::Url;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub(crate) struct TryBytes(Bytes);
@@ -54,8 +59,10 @@ macro_rules! impl_tryfrombuf {
     };
 }
 
+impl_tryfrombuf!(u16, 2, buf, buf.get_u16());
 impl_tryfrombuf!(u32, 4, buf, buf.get_u32());
 impl_tryfrombuf!(u64, 8, buf, buf.get_u64());
+impl_tryfrombuf!(u128, 16, buf, buf.get_u128());
 
 #[derive(Copy, Clone, Debug, Error, Eq, PartialEq)]
 pub(crate) enum PacketError {
@@ -66,13 +73,36 @@ pub(crate) enum PacketError {
 // Like `FromBencode::from_bencode()`, but it checks that there are no trailing
 // bytes afterwards
 pub(crate) fn decode_bencode<T: FromBencode>(buf: &[u8]) -> Result<T, UnbencodeError> {
-    let mut decoder = Decoder::new(buf).with_max_depth(T::EXPECTED_RECURSION_DEPTH);
+    decode_bencode_with(
+        buf,
+        T::EXPECTED_RECURSION_DEPTH,
+        false,
+        T::decode_bencode_object,
+    )
+}
+
+// Like `decode_bencode()`, but the value is decoded by the given function, and,
+// if `lenient` is true, trailing bytes are logged & ignored instead of being
+// an error
+pub(crate) fn decode_bencode_with<T, F>(
+    buf: &[u8],
+    max_depth: usize,
+    lenient: bool,
+    decode: F,
+) -> Result<T, UnbencodeError>
+where
+    F: FnOnce(Object<'_, '_>) -> Result<T, bendy::decoding::Error>,
+{
+    let mut decoder = Decoder::new(buf).with_max_depth(max_depth);
     let value = match decoder.next_object()? {
-        Some(obj) => T::decode_bencode_object(obj)?,
+        Some(obj) => decode(obj)?,
         None => return Err(UnbencodeError::NoData),
     };
     if !matches!(decoder.next_object(), Ok(None)) {
-        return Err(UnbencodeError::TrailingData);
+        if !lenient {
+            return Err(UnbencodeError::TrailingData);
+        }
+        tracing::info!("Ignoring trailing bytes after bencode structure");
     }
     Ok(value)
 }
@@ -87,6 +117,69 @@ pub(crate) enum UnbencodeError {
     TrailingData,
 }
 
+/// Read values from a file (or standard input), one per line.  Leading and
+/// trailing whitespace is ignored, as are blank lines and lines starting with
+/// `#`.
+pub(crate) fn read_list_file<T: FromStr>(file: &InputArg) -> Result<Vec<T>, ListFileError<T::Err>> {
+    let reader = file.open().map_err(|source| ListFileError::Read {
+        file: file.clone(),
+        source,
+    })?;
+    read_list(file, reader)
+}
+
+pub(crate) fn read_list<T: FromStr, R: BufRead>(
+    file: &InputArg,
+    reader: R,
+) -> Result<Vec<T>, ListFileError<T::Err>> {
+    let mut values = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|source| ListFileError::Read {
+            file: file.clone(),
+            source,
+        })?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let value = line.parse::<T>().map_err(|source| ListFileError::Parse {
+            file: file.clone(),
+            lineno: i + 1,
+            source,
+        })?;
+        values.push(value);
+    }
+    Ok(values)
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum ListFileError<E> {
+    #[error("failed to read {file}")]
+    Read { file: InputArg, source: io::Error },
+    #[error("invalid entry on line {lineno} of {file}")]
+    Parse {
+        file: InputArg,
+        lineno: usize,
+        source: E,
+    },
+}
+
+/// Append a query parameter with the given raw bytes as its value, which
+/// need not be valid UTF-8, percent-encoding them as necessary
+pub(crate) fn add_bytes_query_param(url: &mut Url, key: &str, value: &[u8]) {
+    static SENTINEL: &str = "ADD_BYTES_QUERY_PARAM";
+    url.query_pairs_mut()
+        .encoding_override(Some(&|s| {
+            if s == SENTINEL {
+                Cow::from(value.to_vec())
+            } else {
+                Cow::from(s.as_bytes())
+            }
+        }))
+        .append_pair(key, SENTINEL)
+        .encoding_override(None);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;